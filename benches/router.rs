@@ -0,0 +1,80 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Compare the route lookup with the segment trie and with a linear scan of the
+// routes, like the router did before. Run it with `cargo bench --bench router`
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use wasm_workers_server::router::{match_segments, Route, RouteSegment, RouteTree};
+
+// Number of routes in every run
+const ROUTES: [usize; 4] = [10, 100, 1_000, 10_000];
+
+// A project with static, dynamic and catch-all routes
+fn route_segments(count: usize) -> Vec<Vec<RouteSegment>> {
+    (0..count)
+        .map(|i| match i % 3 {
+            0 => format!("/api/v{}/items", i),
+            1 => format!("/api/v{}/items/[id]", i),
+            _ => format!("/api/v{}/files/[...path]", i),
+        })
+        .map(|path| Route::retrieve_segments(&path))
+        .collect()
+}
+
+// Paths that match routes at different positions and a path without a route
+fn url_paths(count: usize) -> Vec<String> {
+    vec![
+        format!("/api/v{}/items", (count - 1) / 3 * 3),
+        format!("/api/v{}/items/42", count / 2 / 3 * 3 + 1),
+        format!("/api/v{}/files/a/b/c", count / 3 / 3 * 3 + 2),
+        String::from("/not/found"),
+    ]
+}
+
+fn route_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("route_lookup");
+
+    for count in ROUTES {
+        let routes = route_segments(count);
+        let paths = url_paths(count);
+
+        let mut tree = RouteTree::default();
+        for (index, segments) in routes.iter().enumerate() {
+            tree.insert(segments, index);
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("segment_trie", count),
+            &paths,
+            |b, paths| {
+                b.iter(|| {
+                    for path in paths.iter() {
+                        black_box(tree.find(path));
+                    }
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("linear_scan", count),
+            &paths,
+            |b, paths| {
+                b.iter(|| {
+                    for path in paths.iter() {
+                        black_box(
+                            routes
+                                .iter()
+                                .position(|segments| match_segments(segments, path).is_some()),
+                        );
+                    }
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, route_lookup);
+criterion_main!(benches);
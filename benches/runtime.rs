@@ -0,0 +1,81 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Compare the cost of preparing a module on every request, like the runner did
+// before, with the pre-linked instances. Run it with `cargo bench --bench runtime`
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::path::Path;
+use wasm_workers_server::config::EngineConfig;
+use wasm_workers_server::runner::Runtime;
+use wasmtime::{Engine, Linker, Module, Store};
+use wasmtime_wasi::{sync::WasiCtxBuilder, WasiCtx};
+
+// A module that does nothing, so the benchmark only measures the cost of
+// preparing and instantiating it
+const EMPTY_MODULE: &str = r#"(module (func (export "_start")))"#;
+
+// A new store for a request. The engine interrupts the modules when they
+// reach the epoch deadline, so the requests never reach it
+fn new_store(engine: &Engine) -> Store<WasiCtx> {
+    let mut store = Store::new(engine, WasiCtxBuilder::new().build());
+    store.set_epoch_deadline(u64::MAX);
+
+    store
+}
+
+fn module_instantiation(c: &mut Criterion) {
+    let config = EngineConfig {
+        cache: Some(false),
+        ..EngineConfig::default()
+    };
+    let runtime = Runtime::new(&config, Path::new("."), None, false).unwrap();
+    let engine = runtime.engine();
+    let module = Module::new(engine, EMPTY_MODULE).unwrap();
+
+    let mut group = c.benchmark_group("module_instantiation");
+
+    // Link WASI and resolve the imports of the module on every request
+    group.bench_function("linker_per_request", |b| {
+        b.iter(|| {
+            let mut linker: Linker<WasiCtx> = Linker::new(engine);
+            wasmtime_wasi::add_to_linker(&mut linker, |s| s).unwrap();
+            let mut store = new_store(engine);
+
+            linker.module(&mut store, "", &module).unwrap();
+            linker
+                .get_default(&mut store, "")
+                .unwrap()
+                .typed::<(), (), _>(&store)
+                .unwrap()
+                .call(&mut store, ())
+                .unwrap();
+        })
+    });
+
+    // Resolve the imports once and only instantiate the module on every request
+    let mut linker: Linker<WasiCtx> = Linker::new(engine);
+    wasmtime_wasi::add_to_linker(&mut linker, |s| s).unwrap();
+    let instance_pre = linker
+        .instantiate_pre(&mut new_store(engine), &module)
+        .unwrap();
+
+    group.bench_function("instance_pre", |b| {
+        b.iter(|| {
+            let mut store = new_store(engine);
+
+            instance_pre
+                .instantiate(&mut store)
+                .unwrap()
+                .get_typed_func::<(), (), _>(&mut store, "_start")
+                .unwrap()
+                .call(&mut store, ())
+                .unwrap();
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, module_instantiation);
+criterion_main!(benches);
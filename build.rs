@@ -0,0 +1,38 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Expose the version of the Wasmtime crate in the lock file as the
+// WASMTIME_VERSION variable. Compiled modules are only valid for the
+// version that compiled them
+fn main() {
+    let lock_file = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_file.display());
+
+    let version = fs::read_to_string(&lock_file)
+        .ok()
+        .and_then(|lock| wasmtime_version(&lock))
+        .unwrap_or_else(|| String::from("unknown"));
+
+    println!("cargo:rustc-env=WASMTIME_VERSION={}", version);
+}
+
+// Find the version of the wasmtime package in the lock file
+fn wasmtime_version(lock: &str) -> Option<String> {
+    let mut lines = lock.lines();
+
+    while let Some(line) = lines.next() {
+        if line == "name = \"wasmtime\"" {
+            return lines
+                .next()?
+                .strip_prefix("version = \"")?
+                .strip_suffix('"')
+                .map(String::from);
+        }
+    }
+
+    None
+}
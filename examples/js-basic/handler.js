@@ -32,7 +32,7 @@ const reply = (request) => {
     <h1>Hello from Wasm Workers Server 👋</h1>
     <pre><code>Replying to ${request.url}
 Method: ${request.method}
-User Agent: ${request.headers.get("userAgent")}
+User Agent: ${request.headers.get("user-agent")}
 Payload: ${request.body || "-"}</code></pre>
     <p>
       This page was generated by a JavaScript file running in WebAssembly.
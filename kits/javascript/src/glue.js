@@ -1,39 +1,395 @@
 // Copyright 2022 VMware, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+const BASE64_CHARS = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Encode the given bytes in base64
+const base64Encode = bytes => {
+  let output = "";
+
+  for (let i = 0; i < bytes.length; i += 3) {
+    const chunk = (bytes[i] << 16) | ((bytes[i + 1] || 0) << 8) | (bytes[i + 2] || 0);
+
+    output += BASE64_CHARS[(chunk >> 18) & 63];
+    output += BASE64_CHARS[(chunk >> 12) & 63];
+    output += i + 1 < bytes.length ? BASE64_CHARS[(chunk >> 6) & 63] : "=";
+    output += i + 2 < bytes.length ? BASE64_CHARS[chunk & 63] : "=";
+  }
+
+  return output;
+};
+
+// Decode the given base64 string
+const base64Decode = text => {
+  const clean = text.replace(/[^A-Za-z0-9+/]/g, "");
+  const bytes = new Uint8Array(Math.floor((clean.length * 3) / 4));
+  let buffer = 0;
+  let bits = 0;
+  let index = 0;
+
+  for (const char of clean) {
+    buffer = (buffer << 6) | BASE64_CHARS.indexOf(char);
+    bits += 6;
+
+    if (bits >= 8) {
+      bits -= 8;
+      bytes[index++] = (buffer >> bits) & 255;
+    }
+  }
+
+  return bytes.subarray(0, index);
+};
+
+class TextEncoder {
+  get encoding() {
+    return "utf-8";
+  }
+
+  encode(text = "") {
+    const bytes = [];
+
+    for (const char of String(text)) {
+      const code = char.codePointAt(0);
+
+      if (code < 0x80) {
+        bytes.push(code);
+      } else if (code < 0x800) {
+        bytes.push(0xc0 | (code >> 6), 0x80 | (code & 63));
+      } else if (code < 0x10000) {
+        bytes.push(0xe0 | (code >> 12), 0x80 | ((code >> 6) & 63), 0x80 | (code & 63));
+      } else {
+        bytes.push(
+          0xf0 | (code >> 18),
+          0x80 | ((code >> 12) & 63),
+          0x80 | ((code >> 6) & 63),
+          0x80 | (code & 63)
+        );
+      }
+    }
+
+    return new Uint8Array(bytes);
+  }
+}
+
+class TextDecoder {
+  get encoding() {
+    return "utf-8";
+  }
+
+  // Invalid sequences are replaced with the U+FFFD character
+  decode(input = new Uint8Array()) {
+    const bytes = toBytes(input);
+    let output = "";
+    let i = 0;
+
+    while (i < bytes.length) {
+      const byte = bytes[i];
+      const length = byte < 0x80 ? 1 : byte >> 5 === 6 ? 2 : byte >> 4 === 14 ? 3 : byte >> 3 === 30 ? 4 : 0;
+      let code = length === 1 ? byte : length === 0 ? -1 : byte & (0xff >> (length + 1));
+
+      for (let j = 1; j < length && code !== -1; j++) {
+        const next = bytes[i + j];
+        code = next !== undefined && next >> 6 === 2 ? (code << 6) | (next & 63) : -1;
+      }
+
+      if (code === -1 || code > 0x10ffff) {
+        output += "\uFFFD";
+        i += 1;
+      } else {
+        output += String.fromCodePoint(code);
+        i += length;
+      }
+    }
+
+    return output;
+  }
+}
+
+// Get the bytes of an ArrayBuffer or a typed array
+const toBytes = input => {
+  if (input instanceof ArrayBuffer) {
+    return new Uint8Array(input);
+  }
+
+  return new Uint8Array(input.buffer, input.byteOffset, input.byteLength);
+};
+
+// Copy the given bytes into a new ArrayBuffer
+const toArrayBuffer = bytes => bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
+
+// Encode a response body for the server. Binary bodies are sent in base64
+const encodeBody = body => {
+  if (body instanceof ArrayBuffer || ArrayBuffer.isView(body)) {
+    return { body: base64Encode(toBytes(body)), encoding: "base64" };
+  }
+
+  return { body: body === undefined || body === null ? "" : String(body), encoding: "utf8" };
+};
+
+// Encode a query component. Spaces are encoded as "+"
+const encodeQueryComponent = text => encodeURIComponent(text).replace(/%20/g, "+");
+
+// Decode a query component. Invalid sequences keep their original text
+const decodeQueryComponent = text => {
+  const plain = text.replace(/\+/g, " ");
+
+  try {
+    return decodeURIComponent(plain);
+  } catch (_err) {
+    return plain;
+  }
+};
+
+class URLSearchParams {
+  constructor(init = "") {
+    this.list = [];
+
+    if (init instanceof URLSearchParams) {
+      this.list = init.list.map(([key, value]) => [key, value]);
+    } else if (Array.isArray(init)) {
+      init.forEach(([key, value]) => this.append(key, value));
+    } else if (typeof init === "object" && init !== null) {
+      for (const key in init) {
+        this.append(key, init[key]);
+      }
+    } else {
+      this.parse(String(init));
+    }
+  }
+
+  // Replace the parameters with the ones in the given query string
+  parse(query) {
+    this.list = [];
+
+    query
+      .replace(/^\?/, "")
+      .split("&")
+      .filter(pair => pair.length > 0)
+      .forEach(pair => {
+        const index = pair.indexOf("=");
+        const key = index === -1 ? pair : pair.slice(0, index);
+        const value = index === -1 ? "" : pair.slice(index + 1);
+
+        this.list.push([decodeQueryComponent(key), decodeQueryComponent(value)]);
+      });
+  }
+
+  get size() {
+    return this.list.length;
+  }
+
+  append(key, value) {
+    this.list.push([String(key), String(value)]);
+  }
+
+  delete(key) {
+    this.list = this.list.filter(([name]) => name !== key);
+  }
+
+  get(key) {
+    const pair = this.list.find(([name]) => name === key);
+    return pair === undefined ? null : pair[1];
+  }
+
+  getAll(key) {
+    return this.list.filter(([name]) => name === key).map(([_name, value]) => value);
+  }
+
+  has(key) {
+    return this.list.some(([name]) => name === key);
+  }
+
+  // Replace the first value and remove the rest
+  set(key, value) {
+    const index = this.list.findIndex(([name]) => name === key);
+
+    if (index === -1) {
+      this.append(key, value);
+    } else {
+      this.list[index][1] = String(value);
+      this.list = this.list.filter(([name], i) => name !== key || i <= index);
+    }
+  }
+
+  sort() {
+    this.list.sort(([a], [b]) => (a < b ? -1 : a > b ? 1 : 0));
+  }
+
+  forEach(callback) {
+    this.list.forEach(([key, value]) => callback(value, key, this));
+  }
+
+  keys() {
+    return this.list.map(([key]) => key)[Symbol.iterator]();
+  }
+
+  values() {
+    return this.list.map(([_key, value]) => value)[Symbol.iterator]();
+  }
+
+  entries() {
+    return this.list.map(([key, value]) => [key, value])[Symbol.iterator]();
+  }
+
+  [Symbol.iterator]() {
+    return this.entries();
+  }
+
+  toString() {
+    return this.list
+      .map(([key, value]) => `${encodeQueryComponent(key)}=${encodeQueryComponent(value)}`)
+      .join("&");
+  }
+}
+
+// Scheme, authority, path, query and fragment of an absolute URL
+const URL_PATTERN = /^([a-zA-Z][a-zA-Z0-9+.-]*:)\/\/([^/?#]*)([^?#]*)(\?[^#]*)?(#.*)?$/;
+
+// Default ports of the special schemes
+const DEFAULT_PORTS = { "http:": "80", "https:": "443", "ws:": "80", "wss:": "443" };
+
+class URL {
+  constructor(url, base) {
+    let href = String(url);
+
+    if (!URL_PATTERN.test(href) && base !== undefined) {
+      href = URL.resolve(href, new URL(base));
+    }
+
+    const match = href.match(URL_PATTERN);
+
+    if (match === null) {
+      throw new TypeError(`Invalid URL: ${href}`);
+    }
+
+    this.protocol = match[1].toLowerCase();
+    // The default port is not part of the host
+    this.host = match[2]
+      .replace(/^.*@/, "")
+      .toLowerCase()
+      .replace(new RegExp(`:${DEFAULT_PORTS[this.protocol]}$`), "");
+    this.pathname = match[3] || "/";
+    this.searchParams = new URLSearchParams(match[4] || "");
+    this.hash = match[5] && match[5] !== "#" ? match[5] : "";
+  }
+
+  // Resolve a relative reference against the given base URL
+  static resolve(url, base) {
+    if (url.startsWith("//")) {
+      return `${base.protocol}${url}`;
+    } else if (url.startsWith("/")) {
+      return `${base.origin}${url}`;
+    } else if (url.startsWith("?")) {
+      return `${base.origin}${base.pathname}${url}`;
+    } else if (url.startsWith("#")) {
+      return `${base.origin}${base.pathname}${base.search}${url}`;
+    }
+
+    const directory = base.pathname.slice(0, base.pathname.lastIndexOf("/") + 1);
+    return `${base.origin}${directory}${url}`;
+  }
+
+  get hostname() {
+    return this.host.replace(/:\d*$/, "");
+  }
+
+  get port() {
+    const match = this.host.match(/:(\d+)$/);
+    return match === null ? "" : match[1];
+  }
+
+  get origin() {
+    return `${this.protocol}//${this.host}`;
+  }
+
+  get search() {
+    const query = this.searchParams.toString();
+    return query.length > 0 ? `?${query}` : "";
+  }
+
+  set search(value) {
+    this.searchParams.parse(String(value));
+  }
+
+  get href() {
+    return `${this.origin}${this.pathname}${this.search}${this.hash}`;
+  }
+
+  toString() {
+    return this.href;
+  }
+
+  toJSON() {
+    return this.href;
+  }
+}
+
+// Header names are case-insensitive. Every header keeps the list of
+// values in order, so repeated headers like Set-Cookie are not lost
 class Headers {
   constructor(initialHeaders) {
-    let headers = {};
+    this.headers = {};
 
-    // Initialize the headers
-    for (const key in initialHeaders) {
-      headers[key] = initialHeaders[key];
+    if (initialHeaders instanceof Headers) {
+      initialHeaders.forEach((value, key) => this.append(key, value));
+    } else if (Array.isArray(initialHeaders)) {
+      // A list of [name, value] pairs
+      initialHeaders.forEach(([key, value]) => this.append(key, value));
+    } else {
+      for (const key in initialHeaders) {
+        [].concat(initialHeaders[key]).forEach(value => this.append(key, value));
+      }
     }
-
-    this.headers = headers;
   }
 
   append(key, value) {
-    this.headers[key] = value;
+    const name = String(key).toLowerCase();
+    (this.headers[name] = this.headers[name] || []).push(String(value));
     return value;
   }
 
   set(key, value) {
-    this.append(key, value);
+    this.headers[String(key).toLowerCase()] = [String(value)];
     return value;
   }
 
   delete(key) {
-    let dropValue = delete this.headers[key];
+    let dropValue = delete this.headers[String(key).toLowerCase()];
     return dropValue;
   }
 
+  has(key) {
+    return String(key).toLowerCase() in this.headers;
+  }
+
+  // All the values joined, like the Fetch API
   get(key) {
-    return this.headers[key];
+    const values = this.headers[String(key).toLowerCase()];
+    return values === undefined ? null : values.join(", ");
   }
 
+  // Set-Cookie values can't be joined
+  getSetCookie() {
+    return (this.headers["set-cookie"] || []).slice();
+  }
+
+  forEach(callback) {
+    for (const key in this.headers) {
+      this.headers[key].forEach(value => callback(value, key, this));
+    }
+  }
+
+  // Headers with a single value are sent as a string and repeated ones
+  // as a list
   toJSON() {
-    return this.headers;
+    let headers = {};
+
+    for (const key in this.headers) {
+      const values = this.headers[key];
+      headers[key] = values.length === 1 ? values[0] : values;
+    }
+
+    return headers;
   }
 }
 
@@ -41,13 +397,48 @@ class Request {
   constructor(input) {
     this.url = input.url;
     this.method = input.method;
-    this.headers = new Headers(input.headers || {});
-    this.body = input.body;
+    // The raw list keeps the repeated headers
+    this.headers = new Headers(input.raw_headers || input.headers || {});
+    this.params = input.params || {};
+
+    // Details of the client and the connection
+    this.remoteAddr = input.remote_addr || null;
+    this.scheme = input.scheme || "http";
+    this.host = input.host || "";
+    this.httpVersion = input.http_version || "";
+    this.requestId = input.request_id || "";
+    this.cookies = input.cookies || {};
+
+    // The query parameters parsed by the server. Every parameter keeps all its values
+    this.query = new URLSearchParams();
+    for (const key in input.query || {}) {
+      input.query[key].forEach(value => this.query.append(key, value));
+    }
+
+    // Binary bodies come in base64
+    if (input.body_encoding === "base64") {
+      this.bytes = base64Decode(input.body || "");
+      this.body = new TextDecoder().decode(this.bytes);
+    } else {
+      this.body = input.body || "";
+    }
+
+    // Extra information added by the middlewares
+    this.context = input.context || {};
+  }
+
+  // The full URL of the request, including the scheme and the host
+  get fullUrl() {
+    return new URL(this.url, `${this.scheme}://${this.host || "localhost"}`);
   }
 
   text() {
     return this.body;
   }
+
+  arrayBuffer() {
+    return toArrayBuffer(this.bytes || new TextEncoder().encode(this.body));
+  }
 }
 
 class Response {
@@ -88,6 +479,22 @@ class Response {
     return response;
   }
 
+  text() {
+    if (this.body instanceof ArrayBuffer || ArrayBuffer.isView(this.body)) {
+      return new TextDecoder().decode(this.body);
+    }
+
+    return encodeBody(this.body).body;
+  }
+
+  arrayBuffer() {
+    if (this.body instanceof ArrayBuffer || ArrayBuffer.isView(this.body)) {
+      return toArrayBuffer(toBytes(this.body));
+    }
+
+    return toArrayBuffer(new TextEncoder().encode(this.text()));
+  }
+
   toString() {
     return this.body;
   }
@@ -117,9 +524,22 @@ const requestToHandler = input => {
   const request = new Request(input);
   const event = {
     request,
+    // Failure details for not found and error handlers
+    error: input.error,
     response: {},
+    // Set when a middleware passes the request to the next stage
+    nextRequest: undefined,
     respondWith(res) {
       this.response = res;
+    },
+    // Middlewares only. Continue with the next stage. It includes the changes
+    // to the request and extra context for the next stages
+    next(context = {}) {
+      this.nextRequest = {
+        url: this.request.url,
+        headers: this.request.headers.toJSON(),
+        context: Object.assign(this.request.context, context)
+      };
     }
   };
 
@@ -127,13 +547,24 @@ const requestToHandler = input => {
 
   handlerFunction(event);
 
+  if (event.nextRequest !== undefined) {
+    return {
+      request: event.nextRequest,
+      kv: Cache.state
+    };
+  }
+
+  const { body, encoding } = encodeBody(event.response.body);
+
   return {
-    body: event.response.body,
-    headers: event.response.headers.headers,
+    body,
+    body_encoding: encoding,
+    headers: event.response.headers.toJSON(),
     status: event.response.status,
     kv: Cache.state
   };
 };
 
-// This is the entrypoint for the project.
-entrypoint = requestToHandler;
\ No newline at end of file
+// This is the entrypoint for the project. It receives and returns JSON
+// strings, so the engine doesn't change the property names
+entrypoint = input => JSON.stringify(requestToHandler(JSON.parse(input)));
\ No newline at end of file
@@ -1,36 +1,144 @@
 // Copyright 2022 VMware, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use quickjs_wasm_rs::{json, Context};
-use std::io::{self, stdin, stdout, Read, Write};
+use anyhow::Result;
+use quickjs_wasm_rs::Context;
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{stdin, stdout, Read, Write};
 
 // JS polyfill
 static POLYFILL: &str = include_str!("./glue.js");
 
-// Separator between source code and request data
-static SEPARATOR: &str = "[[[input]]]";
+// Argument to compile the handler source to bytecode
+static COMPILE_FLAG: &str = "--compile";
+
+// Size of the bytecode length before the bytecode and the request data
+const LENGTH_SIZE: usize = 4;
+
+// Handler source when wws pre-initializes the engine
+static SNAPSHOT_SOURCE: &str = "/src/handler.js";
+
+// Protocol version of the input and output messages. wws reads it from the
+// "wws_protocol" custom section of the engine
+#[cfg_attr(target_arch = "wasm32", link_section = "wws_protocol")]
+#[used]
+static PROTOCOL_VERSION: [u8; 1] = *b"2";
+
+thread_local! {
+    // The engine with the polyfill and the handler source already evaluated.
+    // It's only available in the snapshots
+    static CONTEXT: RefCell<Option<Context>> = RefCell::new(None);
+}
+
+/// Evaluate the handler source before taking the snapshot. wws runs this function
+/// when it loads the handler, so the requests only need to call the entrypoint.
+#[export_name = "wizer.initialize"]
+pub extern "C" fn init() {
+    let source = fs::read_to_string(SNAPSHOT_SOURCE).unwrap();
+    let context = load_context(&source);
+
+    CONTEXT.with(|cell| *cell.borrow_mut() = Some(context));
+}
+
+// Initialize an empty engine
+fn new_context() -> Context {
+    let context = Context::default();
+    register_console(&context).unwrap();
 
-fn main() {
-    let mut context = Context::default();
     context
-        .register_globals(io::stderr(), io::stderr())
-        .unwrap();
+}
+
+// Add the console.log and console.error functions. Both write to STDERR, as
+// STDOUT contains the response
+fn register_console(context: &Context) -> Result<()> {
+    let console = context.object_value()?;
+
+    for name in ["log", "error"] {
+        let function = context.wrap_callback(|context, _this, args| {
+            let line: Vec<String> = args
+                .iter()
+                .map(|arg| arg.as_str_lossy().into_owned())
+                .collect();
+            eprintln!("{}", line.join(" "));
+
+            context.undefined_value()
+        })?;
+        console.set_property(name, function)?;
+    }
 
+    context.global_object()?.set_property("console", console)
+}
+
+// The polyfill with the handler source. The engine skips the last character
+// of the source, so it ends with a new line
+fn handler_source(source: &str) -> String {
     let mut contents = String::new();
-    let mut source = String::new();
-    contents.push_str(&POLYFILL);
+    contents.push_str(POLYFILL);
+    contents.push_str(source);
+    contents.push('\n');
+
+    contents
+}
+
+// Initialize the engine and evaluate the polyfill with the handler source
+fn load_context(source: &str) -> Context {
+    let context = new_context();
+    let _ = context
+        .eval_global("handler.js", &handler_source(source))
+        .unwrap();
+
+    context
+}
 
+// Compile the polyfill with the handler source from STDIN and write the
+// bytecode to STDOUT. wws runs it once when it loads the handler
+fn compile() {
+    let mut source = String::new();
     stdin().read_to_string(&mut source).unwrap();
-    let chunks: Vec<&str> = source.split(SEPARATOR).collect();
 
-    contents.push_str(&chunks.first().unwrap());
+    let bytecode = new_context()
+        .compile_global("handler.js", &handler_source(&source))
+        .unwrap();
+
+    stdout()
+        .write_all(&bytecode)
+        .expect("Error when returning the bytecode");
+    stdout().flush().expect("Error when returning the bytecode");
+}
+
+fn main() {
+    if env::args().any(|arg| arg == COMPILE_FLAG) {
+        return compile();
+    }
+
+    let mut stdin_contents = Vec::new();
+    stdin().read_to_end(&mut stdin_contents).unwrap();
+
+    // Without a snapshot, the handler bytecode comes before the request data
+    let (context, input) = match CONTEXT.with(|cell| cell.borrow_mut().take()) {
+        Some(context) => (context, stdin_contents.as_slice()),
+        None => {
+            let (length, rest) = stdin_contents.split_at(LENGTH_SIZE);
+            let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+            let (bytecode, input) = rest.split_at(length);
+
+            let context = new_context();
+            let _ = context.eval_binary(bytecode).unwrap();
+
+            (context, input)
+        }
+    };
 
-    let _ = context.eval_global("handler.js", &contents).unwrap();
     let global = context.global_object().unwrap();
     let entrypoint = global.get_property("entrypoint").unwrap();
 
-    let input_bytes = &chunks.last().unwrap().as_bytes();
-    let input_value = json::transcode_input(&context, &input_bytes).unwrap();
+    // The input and output are JSON strings. The polyfill parses and serializes
+    // them, so the property names like the headers keep their original case
+    let input_value = context
+        .value_from_str(std::str::from_utf8(input).unwrap())
+        .unwrap();
 
     // Run the handler to get the output
     let output_value = match entrypoint.call(&global, &[input_value]) {
@@ -38,10 +146,10 @@ fn main() {
         Err(err) => panic!("{}", err.to_string()),
     };
 
-    let output = json::transcode_output(output_value).unwrap();
+    let output = output_value.as_str().unwrap();
 
     stdout()
-        .write(&output)
+        .write(output.as_bytes())
         .expect("Error when returning the response");
     stdout().flush().expect("Error when returning the response");
 }
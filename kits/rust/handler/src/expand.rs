@@ -6,27 +6,37 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::parse_macro_input;
 
-/// Expand the given input after processing by the macro
-pub fn expand_macro(attr: TokenStream, item: TokenStream) -> TokenStream {
+/// Expand the given input after processing by the macro. Middlewares
+/// return a different output, so they use a different serialization
+pub fn expand_macro(attr: TokenStream, item: TokenStream, middleware: bool) -> TokenStream {
     let handler_fn = parse_macro_input!(item as syn::ItemFn);
     let handler_fn_name = &handler_fn.sig.ident;
     let args = parse_macro_input!(attr as Args);
-    let func_call;
-
-    if args.has_cache() {
-        func_call = quote! {
+    let func_call = if args.has_cache() {
+        quote! {
             #handler_fn_name(input.to_http_request(), &mut cache)
         }
     } else {
-        func_call = quote! {
+        quote! {
             #handler_fn_name(input.to_http_request())
         }
-    }
+    };
+
+    let to_json = if middleware {
+        quote! { wasm_workers_rs::io::middleware_to_json(response, cache) }
+    } else {
+        quote! { Output::from_response(response, cache).to_json() }
+    };
 
     let main_fn = quote! {
         use wasm_workers_rs::io::{Input, Output};
         use std::io::stdin;
 
+        // Declare the protocol version of the input and output messages
+        #[cfg_attr(target_arch = "wasm32", link_section = "wws_protocol")]
+        #[used]
+        static WWS_PROTOCOL: [u8; 1] = wasm_workers_rs::io::PROTOCOL_VERSION;
+
         fn main() {
             let input = Input::new(stdin());
             let error = Output::new(
@@ -40,7 +50,7 @@ pub fn expand_macro(attr: TokenStream, item: TokenStream) -> TokenStream {
                 let mut cache = input.cache_data();
 
                 if let Ok(response) = #func_call {
-                    match Output::from_response(response, cache).to_json() {
+                    match #to_json {
                         Ok(res) => println!("{}", res),
                         Err(_) => println!("{}", error)
                     }
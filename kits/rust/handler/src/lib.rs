@@ -10,5 +10,12 @@ use proc_macro::TokenStream;
 // with Request and Response objects
 #[proc_macro_attribute]
 pub fn handler(attr: TokenStream, item: TokenStream) -> TokenStream {
-    expand::expand_macro(attr, item)
+    expand::expand_macro(attr, item, false)
+}
+
+// Middleware entrypoint. The function receives the Request and
+// returns a Middleware result to continue or reply
+#[proc_macro_attribute]
+pub fn middleware(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand::expand_macro(attr, item, true)
 }
@@ -0,0 +1,102 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Encoding of the request and response bodies in the JSON messages. Text
+/// bodies keep their contents, while binary ones are encoded in base64.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    /// UTF-8 text
+    Utf8,
+    /// Binary content encoded in base64
+    Base64,
+}
+
+impl Default for BodyEncoding {
+    fn default() -> Self {
+        Self::Utf8
+    }
+}
+
+impl BodyEncoding {
+    /// Decode the given body
+    pub fn decode(&self, body: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Utf8 => Ok(body.as_bytes().to_vec()),
+            Self::Base64 => base64::decode(body).map_err(anyhow::Error::new),
+        }
+    }
+}
+
+/// The body of the requests and responses. Handlers use `String` for text
+/// bodies and `Vec<u8>` for binary ones, like images or compressed files:
+///
+/// ```ignore
+/// #[handler]
+/// fn handler(req: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+///     Ok(Response::builder()
+///         .header("Content-Type", "image/png")
+///         .body(include_bytes!("logo.png").to_vec())?)
+/// }
+/// ```
+pub trait Body: Sized {
+    /// Build the body from the request contents
+    fn from_bytes(bytes: Vec<u8>) -> Self;
+
+    /// Encode the body for the JSON output
+    fn encode(self) -> (String, BodyEncoding);
+}
+
+impl Body for String {
+    /// Invalid UTF-8 sequences are replaced
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+        }
+    }
+
+    fn encode(self) -> (String, BodyEncoding) {
+        (self, BodyEncoding::Utf8)
+    }
+}
+
+impl Body for Vec<u8> {
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        bytes
+    }
+
+    fn encode(self) -> (String, BodyEncoding) {
+        (base64::encode(self), BodyEncoding::Base64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_encoding() {
+        assert_eq!(
+            String::from("Hello").encode(),
+            (String::from("Hello"), BodyEncoding::Utf8)
+        );
+        assert_eq!(
+            vec![0xff, 0x00, 0x61].encode(),
+            (String::from("/wBh"), BodyEncoding::Base64)
+        );
+
+        assert_eq!(BodyEncoding::Utf8.decode("/wBh").unwrap(), b"/wBh");
+        assert_eq!(
+            BodyEncoding::Base64.decode("/wBh").unwrap(),
+            [0xff, 0x00, 0x61]
+        );
+        assert!(BodyEncoding::Base64.decode("not base64!").is_err());
+
+        // Text bodies replace the invalid UTF-8 sequences
+        assert_eq!(String::from_bytes(vec![0xff, 0x00, 0x61]), "\u{fffd}\0a");
+    }
+}
@@ -1,42 +1,186 @@
 // Copyright 2022 VMware, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::body::{Body, BodyEncoding};
+use crate::middleware::{Context, Middleware};
+use crate::params::{Param, Params};
+use crate::request::{Cookies, Query, RequestInfo};
 use anyhow::Result;
-use http::Response;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::{Request, Response};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use std::collections::HashMap;
 use std::io::Stdin;
 
+/// Protocol version of the JSON messages between wws and the handlers. The
+/// `handler` and `middleware` macros declare it in the `wws_protocol` custom
+/// section of the module.
+pub const PROTOCOL_VERSION: [u8; 1] = *b"2";
+
 /// Represents the JSON data that will be injected by the
 /// main project.
 #[derive(Serialize, Deserialize)]
 pub struct Input {
     url: String,
     method: String,
+    #[serde(default)]
+    query: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    cookies: HashMap<String, String>,
+    #[serde(default)]
+    remote_addr: Option<String>,
+    #[serde(default)]
+    scheme: String,
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    http_version: String,
+    #[serde(default)]
+    request_id: String,
     headers: HashMap<String, String>,
+    #[serde(default)]
+    raw_headers: Vec<(String, String)>,
     body: String,
+    #[serde(default)]
+    body_encoding: BodyEncoding,
     kv: HashMap<String, String>,
+    #[serde(default)]
+    params: HashMap<String, Param>,
+    #[serde(default)]
+    error: Option<ErrorDetails>,
+    #[serde(default)]
+    context: HashMap<String, String>,
+}
+
+/// The values of a header. Headers that appear several times,
+/// like `Set-Cookie`, contain a list of values.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum HeaderValues {
+    /// A single value
+    One(String),
+    /// Multiple values in order
+    Many(Vec<String>),
+}
+
+impl HeaderValues {
+    /// Collect the values of every header. Values that are not valid
+    /// UTF-8 are skipped
+    pub fn from_header_map(headers: &HeaderMap) -> HashMap<String, HeaderValues> {
+        let mut parsed = HashMap::new();
+
+        for key in headers.keys() {
+            let mut values: Vec<String> = headers
+                .get_all(key)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(String::from)
+                .collect();
+
+            let values = match values.len() {
+                0 => continue,
+                1 => HeaderValues::One(values.remove(0)),
+                _ => HeaderValues::Many(values),
+            };
+
+            parsed.insert(String::from(key.as_str()), values);
+        }
+
+        parsed
+    }
+}
+
+/// Details of a failure. They are only available in not found (`_404`)
+/// and error (`_error`) handlers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorDetails {
+    /// The HTTP status of the failure
+    pub status: u16,
+    /// A description of the failure
+    pub message: String,
+    /// The original URL path of the request
+    pub path: String,
 }
 
 impl Input {
     /// Build the object from a JSON input
     pub fn new(reader: Stdin) -> Result<Self> {
-        serde_json::from_reader::<Stdin, Input>(reader).map_err(|e| anyhow::Error::new(e))
+        serde_json::from_reader::<Stdin, Input>(reader).map_err(anyhow::Error::new)
     }
 
     /// Convers the current object to a valid http::Request
-    /// object
-    pub fn to_http_request(&self) -> http::Request<String> {
+    /// object. The route params, the query, the cookies, the request info,
+    /// the middlewares context and the failure details (if available) are
+    /// included as request extensions. The body
+    /// is a `String` or a `Vec<u8>` for binary contents
+    pub fn to_http_request<B: Body>(&self) -> http::Request<B> {
         let mut request = http::request::Builder::new()
             .uri(&self.url)
             .method(self.method.as_str());
 
-        for (key, value) in self.headers.iter() {
-            request = request.header(key, value);
+        // Older servers only send the joined headers
+        let headers = if self.raw_headers.is_empty() {
+            self.headers.clone().into_iter().collect()
+        } else {
+            self.raw_headers.clone()
+        };
+
+        // Skip the invalid headers instead of failing
+        for (key, value) in headers.iter() {
+            if let (Ok(key), Ok(value)) = (
+                HeaderName::from_bytes(key.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                request = request.header(key, value);
+            }
+        }
+
+        let mut request = request.body(B::from_bytes(self.body())).unwrap();
+        request.extensions_mut().insert(self.params());
+        request.extensions_mut().insert(self.query());
+        request.extensions_mut().insert(self.cookies());
+        request.extensions_mut().insert(self.info());
+        request
+            .extensions_mut()
+            .insert(Context::new(self.context.clone()));
+
+        if let Some(error) = &self.error {
+            request.extensions_mut().insert(error.clone());
         }
 
-        request.body(self.body.to_string()).unwrap()
+        request
+    }
+
+    /// Retrieve the request body. Invalid binary bodies are empty
+    pub fn body(&self) -> Vec<u8> {
+        self.body_encoding.decode(&self.body).unwrap_or_default()
+    }
+
+    /// Retrieve the values of the dynamic and catch-all segments of the route
+    pub fn params(&self) -> Params {
+        Params::new(self.params.clone())
+    }
+
+    /// Retrieve the parameters of the URL query
+    pub fn query(&self) -> Query {
+        Query::new(self.query.clone())
+    }
+
+    /// Retrieve the cookies of the request
+    pub fn cookies(&self) -> Cookies {
+        Cookies::new(self.cookies.clone())
+    }
+
+    /// Retrieve the details about the client and the connection
+    pub fn info(&self) -> RequestInfo {
+        RequestInfo {
+            remote_addr: self.remote_addr.clone(),
+            scheme: self.scheme.clone(),
+            host: self.host.clone(),
+            http_version: self.http_version.clone(),
+            request_id: self.request_id.clone(),
+        }
     }
 
     /// Retrieve the Key/Value data
@@ -50,7 +194,9 @@ impl Input {
 #[derive(Serialize, Deserialize)]
 pub struct Output {
     body: String,
-    headers: HashMap<String, String>,
+    #[serde(default)]
+    body_encoding: BodyEncoding,
+    headers: HashMap<String, HeaderValues>,
     status: u16,
     kv: HashMap<String, String>,
 }
@@ -65,37 +211,221 @@ impl Output {
     ) -> Self {
         Self {
             body: body.to_string(),
-            status: status,
-            headers: headers.unwrap_or_else(|| HashMap::new()),
-            kv: kv.unwrap_or_else(|| HashMap::new()),
+            body_encoding: BodyEncoding::Utf8,
+            status,
+            headers: headers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(key, value)| (key, HeaderValues::One(value)))
+                .collect(),
+            kv: kv.unwrap_or_default(),
         }
     }
 
-    /// Build the struct from a http::Response object
-    pub fn from_response(response: Response<String>, cache: HashMap<String, String>) -> Self {
-        let mut headers = HashMap::new();
-
-        for (key, value) in response.headers().iter() {
-            headers.insert(
-                String::from(key.as_str()),
-                String::from(value.to_str().unwrap()),
-            );
-        }
+    /// Build the struct from a http::Response object. Binary bodies
+    /// are encoded in base64
+    pub fn from_response<B: Body>(response: Response<B>, cache: HashMap<String, String>) -> Self {
+        let headers = HeaderValues::from_header_map(response.headers());
 
         // Note: added status here because `into_body` takes ownership of the
         // response
         let status = response.status().as_u16();
 
-        Self::new(
-            response.into_body().as_str(),
+        let (body, body_encoding) = response.into_body().encode();
+
+        Self {
+            body,
+            body_encoding,
             status,
-            Some(headers),
-            Some(cache.clone()),
-        )
+            headers,
+            kv: cache,
+        }
+    }
+
+    /// Convert it to JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(&self).map_err(anyhow::Error::new)
+    }
+}
+
+/// The changes that a middleware applies to the request
+#[derive(Serialize, Deserialize)]
+pub struct RequestChanges {
+    url: String,
+    headers: HashMap<String, HeaderValues>,
+    context: HashMap<String, String>,
+}
+
+/// Represents the JSON output of a middleware that passes
+/// the request to the next stage
+#[derive(Serialize, Deserialize)]
+pub struct NextOutput {
+    request: RequestChanges,
+    kv: HashMap<String, String>,
+}
+
+impl NextOutput {
+    /// Build the struct from a http::Request object
+    pub fn from_request(request: Request<String>, cache: HashMap<String, String>) -> Self {
+        let headers = HeaderValues::from_header_map(request.headers());
+
+        let context = request
+            .extensions()
+            .get::<Context>()
+            .map(|context| context.all().clone())
+            .unwrap_or_default();
+
+        Self {
+            request: RequestChanges {
+                url: request.uri().to_string(),
+                headers,
+                context,
+            },
+            kv: cache,
+        }
     }
 
     /// Convert it to JSON
     pub fn to_json(&self) -> Result<String> {
-        serde_json::to_string(&self).map_err(|e| anyhow::Error::new(e))
+        serde_json::to_string(&self).map_err(anyhow::Error::new)
+    }
+}
+
+/// Convert the result of a middleware to JSON
+pub fn middleware_to_json(result: Middleware, cache: HashMap<String, String>) -> Result<String> {
+    match result {
+        Middleware::Next(request) => NextOutput::from_request(request, cache).to_json(),
+        Middleware::Respond(response) => Output::from_response(response, cache).to_json(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    // The golden messages of wws. They describe the same request and response
+    // in every protocol version
+    const INPUT_V1: &str =
+        include_str!("../../../src/runner/fixtures/protocol/v1/input_extended.json");
+    const INPUT_V2: &str = include_str!("../../../src/runner/fixtures/protocol/v2/input.json");
+    const OUTPUT_V1: &str = include_str!("../../../src/runner/fixtures/protocol/v1/output.json");
+    const OUTPUT_V2: &str = include_str!("../../../src/runner/fixtures/protocol/v2/output.json");
+
+    fn json(contents: &str) -> Value {
+        serde_json::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn input_v1_golden() {
+        let input: Input = serde_json::from_str(INPUT_V1).unwrap();
+        let request = input.to_http_request::<String>();
+
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.uri(), "/search?q=wasm&tag=a&tag=b");
+        assert_eq!(request.headers()["cookie"], "session=abc");
+        assert_eq!(request.body(), "\u{fffd}\0a");
+
+        // The version 1 doesn't include the parsed query and the client details
+        let query = request.extensions().get::<Query>().unwrap();
+        assert!(query.all().is_empty());
+        assert_eq!(
+            request.extensions().get::<RequestInfo>().unwrap().scheme,
+            ""
+        );
+    }
+
+    #[test]
+    fn input_v2_golden() {
+        let input: Input = serde_json::from_str(INPUT_V2).unwrap();
+        let request = input.to_http_request::<Vec<u8>>();
+
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.uri(), "/search?q=wasm&tag=a&tag=b");
+        assert_eq!(request.headers()["host"], "localhost:8080");
+        assert_eq!(request.body(), &vec![0xff, 0x00, 0x61]);
+
+        let query = request.extensions().get::<Query>().unwrap();
+        assert_eq!(query.get("q"), Some("wasm"));
+        assert_eq!(query.get_all("tag"), ["a", "b"]);
+
+        let cookies = request.extensions().get::<Cookies>().unwrap();
+        assert_eq!(cookies.get("session"), Some("abc"));
+
+        let info = request.extensions().get::<RequestInfo>().unwrap();
+        assert_eq!(info.remote_addr.as_deref(), Some("127.0.0.1:4000"));
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host, "localhost:8080");
+        assert_eq!(info.http_version, "HTTP/1.1");
+        assert_eq!(info.request_id, "golden");
+
+        // The input keeps every field after a round-trip. wws skips the
+        // missing error details
+        let mut expected = json(INPUT_V2);
+        expected["error"] = Value::Null;
+        assert_eq!(serde_json::to_value(&input).unwrap(), expected);
+    }
+
+    #[test]
+    fn output_v1_golden() {
+        let headers = HashMap::from([(
+            String::from("x_generated_by"),
+            String::from("wasm-workers-server"),
+        )]);
+        let output = Output::new("Hello", 200, Some(headers), None);
+
+        // The version 2 adds the body encoding. The rest of the fields don't change
+        let mut expected = json(OUTPUT_V1);
+        expected["body_encoding"] = Value::from("utf8");
+        assert_eq!(json(&output.to_json().unwrap()), expected);
+    }
+
+    #[test]
+    fn output_v2_golden() {
+        let response = Response::builder()
+            .status(200)
+            .header("set-cookie", "a=1")
+            .header("set-cookie", "b=2")
+            .header("X_Custom_Id", "1")
+            .body(vec![0xff, 0x00, 0x61])
+            .unwrap();
+        let output = Output::from_response(response, HashMap::new());
+
+        // HTTP header names are lowercase
+        let mut expected = json(OUTPUT_V2);
+        let headers = expected["headers"].as_object_mut().unwrap();
+        let custom_id = headers.remove("X_Custom_Id").unwrap();
+        headers.insert(String::from("x_custom_id"), custom_id);
+
+        assert_eq!(json(&output.to_json().unwrap()), expected);
+    }
+
+    #[test]
+    fn middleware_output() {
+        let input: Input = serde_json::from_str(INPUT_V2).unwrap();
+        let mut request = input.to_http_request::<String>();
+        request
+            .headers_mut()
+            .insert("x-user", HeaderValue::from_static("admin"));
+        request
+            .extensions_mut()
+            .get_mut::<Context>()
+            .unwrap()
+            .set("user", "admin");
+
+        let next = json(&middleware_to_json(Middleware::Next(request), HashMap::new()).unwrap());
+        assert_eq!(next["request"]["url"], "/search?q=wasm&tag=a&tag=b");
+        assert_eq!(next["request"]["headers"]["x-user"], "admin");
+        assert_eq!(next["request"]["context"]["user"], "admin");
+
+        let response = Response::builder()
+            .status(401)
+            .body(String::from("Unauthorized"))
+            .unwrap();
+        let respond =
+            json(&middleware_to_json(Middleware::Respond(response), HashMap::new()).unwrap());
+        assert_eq!(respond["status"], 401);
+        assert_eq!(respond["body"], "Unauthorized");
+        assert!(respond.get("request").is_none());
     }
 }
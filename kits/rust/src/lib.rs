@@ -1,9 +1,13 @@
 // Copyright 2022 VMware, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod body;
 pub mod cache;
 pub mod io;
+pub mod middleware;
+pub mod params;
+pub mod request;
 
-pub use handler::handler;
+pub use handler::{handler, middleware};
 // Re-export http
 pub use http;
@@ -0,0 +1,67 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use http::{Request, Response};
+use std::collections::HashMap;
+
+/// Extra information that middlewares add to the request. The next
+/// middlewares and the handler receive it as a request extension:
+///
+/// ```ignore
+/// let user = req.extensions().get::<Context>().and_then(|c| c.get("user"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    values: HashMap<String, String>,
+}
+
+impl Context {
+    /// Build the context from the given values
+    pub fn new(values: HashMap<String, String>) -> Self {
+        Self { values }
+    }
+
+    /// Retrieve the value of the given key if available
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| value.as_str())
+    }
+
+    /// Set a value for the next stages
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(String::from(key), String::from(value));
+    }
+
+    /// Retrieve all the values
+    pub fn all(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+}
+
+/// The result of a middleware (`_middleware.wasm`). It passes the request
+/// to the next stage or replies to it directly.
+pub enum Middleware {
+    /// Continue with the given request. Changes on the URL, the headers
+    /// and the `Context` extension are available in the next stages
+    Next(Request<String>),
+    /// Reply with the given response and skip the next stages
+    Respond(Response<String>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_values() {
+        let mut context = Context::new(HashMap::from([(
+            String::from("user"),
+            String::from("guest"),
+        )]));
+        context.set("user", "admin");
+        context.set("role", "owner");
+
+        assert_eq!(context.get("user"), Some("admin"));
+        assert_eq!(context.get("role"), Some("owner"));
+        assert_eq!(context.all().len(), 2);
+    }
+}
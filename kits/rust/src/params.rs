@@ -0,0 +1,55 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The value of a route param. Dynamic segments (`[id]`) capture a single
+/// path segment, while catch-all segments (`[...slug]`) capture the rest of the path.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Param {
+    Segment(String),
+    Rest(Vec<String>),
+}
+
+/// The values of the dynamic segments of the route. For example, a `users/[id].wasm`
+/// handler receives the `id` param when replying to `/users/42`.
+///
+/// Params are available as an extension of the incoming request:
+///
+/// ```ignore
+/// let id = req.extensions().get::<Params>().and_then(|p| p.get("id"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Params {
+    values: HashMap<String, Param>,
+}
+
+impl Params {
+    /// Build the params from the given values
+    pub fn new(values: HashMap<String, Param>) -> Self {
+        Self { values }
+    }
+
+    /// Retrieve the value of the given dynamic segment if available
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match self.values.get(key)? {
+            Param::Segment(value) => Some(value.as_str()),
+            Param::Rest(_) => None,
+        }
+    }
+
+    /// Retrieve the path segments captured by the given catch-all segment if available
+    pub fn get_rest(&self, key: &str) -> Option<&[String]> {
+        match self.values.get(key)? {
+            Param::Rest(values) => Some(values.as_slice()),
+            Param::Segment(_) => None,
+        }
+    }
+
+    /// Retrieve all the params
+    pub fn all(&self) -> &HashMap<String, Param> {
+        &self.values
+    }
+}
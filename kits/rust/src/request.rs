@@ -0,0 +1,121 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+/// The parameters of the URL query. Parameters that appear several times
+/// keep all their values in order.
+///
+/// The query is available as an extension of the incoming request:
+///
+/// ```ignore
+/// let page = req.extensions().get::<Query>().and_then(|q| q.get("page"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    values: HashMap<String, Vec<String>>,
+}
+
+impl Query {
+    /// Build the query from the given values
+    pub fn new(values: HashMap<String, Vec<String>>) -> Self {
+        Self { values }
+    }
+
+    /// Retrieve the first value of the given parameter if available
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key)?.first().map(|value| value.as_str())
+    }
+
+    /// Retrieve all the values of the given parameter
+    pub fn get_all(&self, key: &str) -> &[String] {
+        self.values
+            .get(key)
+            .map(|values| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Retrieve all the parameters
+    pub fn all(&self) -> &HashMap<String, Vec<String>> {
+        &self.values
+    }
+}
+
+/// The cookies of the request. They are available as an extension of
+/// the incoming request:
+///
+/// ```ignore
+/// let session = req.extensions().get::<Cookies>().and_then(|c| c.get("session"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Cookies {
+    values: HashMap<String, String>,
+}
+
+impl Cookies {
+    /// Build the cookies from the given values
+    pub fn new(values: HashMap<String, String>) -> Self {
+        Self { values }
+    }
+
+    /// Retrieve the value of the given cookie if available
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|value| value.as_str())
+    }
+
+    /// Retrieve all the cookies
+    pub fn all(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+}
+
+/// Details about the client and the connection. They are available as an
+/// extension of the incoming request:
+///
+/// ```ignore
+/// let info = req.extensions().get::<RequestInfo>().unwrap();
+/// println!("{} from {:?}", info.request_id, info.remote_addr);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RequestInfo {
+    /// Address of the client if available
+    pub remote_addr: Option<String>,
+    /// Scheme of the request (http or https)
+    pub scheme: String,
+    /// Host of the request, including the port if it's present
+    pub host: String,
+    /// HTTP version of the request, like "HTTP/1.1"
+    pub http_version: String,
+    /// Unique identifier of the request. The middlewares and the handler
+    /// receive the same one
+    pub request_id: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_values() {
+        let query = Query::new(HashMap::from([(
+            String::from("tag"),
+            vec![String::from("a"), String::from("b")],
+        )]));
+
+        assert_eq!(query.get("tag"), Some("a"));
+        assert_eq!(query.get_all("tag"), ["a", "b"]);
+        assert_eq!(query.get("page"), None);
+        assert!(query.get_all("page").is_empty());
+    }
+
+    #[test]
+    fn cookie_values() {
+        let cookies = Cookies::new(HashMap::from([(
+            String::from("session"),
+            String::from("abc"),
+        )]));
+
+        assert_eq!(cookies.get("session"), Some("abc"));
+        assert_eq!(cookies.get("theme"), None);
+    }
+}
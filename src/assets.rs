@@ -0,0 +1,427 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//
+// Serve the static assets of the project. They are placed
+// in the public folder
+//
+use actix_web::{
+    http::{header, Method, StatusCode},
+    web, HttpRequest, HttpResponse,
+};
+use percent_encoding::percent_decode_str;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The folder that contains the static assets of the project
+pub const PUBLIC_FOLDER: &str = "public";
+
+/// Precompressed variants of the assets. They are served when the client
+/// supports the encoding. The first one takes precedence.
+const ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// The range of bytes requested by the client
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// Send the entire file
+    Full,
+    /// Send the bytes from start to end (both included)
+    Partial(u64, u64),
+    /// The range is not valid for the file
+    Unsatisfiable,
+}
+
+/// The static assets of the project. Files in the `public` folder are served
+/// in the same path. For example, `public/css/main.css` replies to `/css/main.css`.
+pub struct StaticAssets {
+    /// The public folder
+    root: PathBuf,
+}
+
+impl StaticAssets {
+    /// Initialize the static assets from the given project folder. It returns None
+    /// if there's no public folder
+    pub fn new(base_path: &Path) -> Option<Self> {
+        let root = base_path.join(PUBLIC_FOLDER);
+
+        if root.is_dir() {
+            Some(Self { root })
+        } else {
+            None
+        }
+    }
+
+    /// Serve the static asset that matches the URL path if available. The path may
+    /// differ from the request one when a middleware rewrites it. Only GET and HEAD
+    /// requests are allowed. Note the server drops the body for HEAD requests.
+    pub async fn serve(&self, req: &HttpRequest, url_path: &str) -> Option<HttpResponse> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return None;
+        }
+
+        let path = self.resolve(url_path)?;
+        let accept_encoding = header_str(req, header::ACCEPT_ENCODING).unwrap_or("");
+        let (file_path, encoding, has_variants) = select_variant(&path, accept_encoding);
+        let metadata = fs::metadata(&file_path).ok()?;
+        let size = metadata.len();
+        let modified = metadata.modified().ok();
+        let etag = build_etag(size, modified);
+
+        let mut builder = HttpResponse::Ok();
+        builder
+            .insert_header((header::CONTENT_TYPE, content_type(&path)))
+            .insert_header((header::ETAG, etag.as_str()))
+            .insert_header((header::ACCEPT_RANGES, "bytes"));
+
+        if let Some(modified) = modified {
+            builder.insert_header(header::LastModified(modified.into()));
+        }
+        if let Some(encoding) = encoding {
+            builder.insert_header((header::CONTENT_ENCODING, encoding));
+        }
+        if has_variants {
+            builder.insert_header((header::VARY, "Accept-Encoding"));
+        }
+
+        if is_not_modified(req, &etag, modified) {
+            return Some(builder.status(StatusCode::NOT_MODIFIED).finish());
+        }
+
+        let range = match header_str(req, header::RANGE) {
+            // Ignore the range if the asset changed
+            Some(_) if !if_range_matches(req, &etag, modified) => ByteRange::Full,
+            Some(value) => parse_range(value, size),
+            None => ByteRange::Full,
+        };
+
+        let (start, end) = match range {
+            ByteRange::Full => (0, size.saturating_sub(1)),
+            ByteRange::Partial(start, end) => {
+                builder.status(StatusCode::PARTIAL_CONTENT).insert_header((
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, size),
+                ));
+                (start, end)
+            }
+            ByteRange::Unsatisfiable => {
+                return Some(
+                    builder
+                        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                        .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                        .finish(),
+                );
+            }
+        };
+
+        let length = if size == 0 { 0 } else { end + 1 - start };
+        let contents = web::block(move || read_range(&file_path, start, length)).await;
+
+        match contents {
+            Ok(Ok(bytes)) => Some(builder.body(bytes)),
+            _ => Some(HttpResponse::InternalServerError().body("Error reading the file")),
+        }
+    }
+
+    // Find the file in the public folder for the given URL path. Paths that
+    // try to access files outside of the public folder are rejected.
+    fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let mut path = self.root.join(relative_path(url_path)?);
+
+        if path.is_dir() {
+            path.push("index.html");
+        }
+
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+// Convert the URL path into a relative file path. It rejects the segments
+// that may access other folders.
+fn relative_path(url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+    let mut path = PathBuf::new();
+
+    for segment in decoded.split('/').filter(|s| !s.is_empty()) {
+        if segment == "." || segment == ".." || segment.contains(['\\', '\0']) {
+            return None;
+        }
+
+        path.push(segment);
+    }
+
+    Some(path)
+}
+
+// Look for a precompressed variant of the file that the client accepts. It returns
+// the file to serve, its encoding and if the file has precompressed variants.
+fn select_variant(path: &Path, accept_encoding: &str) -> (PathBuf, Option<&'static str>, bool) {
+    let accepted = accepted_encodings(accept_encoding);
+    let mut has_variants = false;
+    let mut selected = None;
+
+    for (encoding, extension) in ENCODINGS.iter() {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(extension);
+        let variant = PathBuf::from(variant);
+
+        if variant.is_file() {
+            has_variants = true;
+
+            if selected.is_none() && accepted.contains(encoding) {
+                selected = Some((variant, *encoding));
+            }
+        }
+    }
+
+    match selected {
+        Some((variant, encoding)) => (variant, Some(encoding), has_variants),
+        None => (path.to_path_buf(), None, has_variants),
+    }
+}
+
+// Parse the Accept-Encoding header. Encodings with a quality of 0 are not accepted
+fn accepted_encodings(header_value: &str) -> Vec<&str> {
+    header_value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(|p| p.trim());
+            let encoding = parts.next().filter(|e| !e.is_empty())?;
+            let disabled = parts.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .map(|q| q == 0.0)
+                    .unwrap_or(false)
+            });
+
+            if disabled {
+                None
+            } else {
+                Some(encoding)
+            }
+        })
+        .collect()
+}
+
+// Parse the Range header. Only single ranges are supported. The entire file is
+// sent for other range units or multiple ranges.
+fn parse_range(header_value: &str, size: u64) -> ByteRange {
+    let range = match header_value.trim().strip_prefix("bytes=") {
+        Some(range) if !range.contains(',') => range.trim(),
+        _ => return ByteRange::Full,
+    };
+
+    let (start, end) = match range.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Full,
+    };
+
+    let bounds = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=0-499
+        (Ok(start), Ok(end)) if start <= end => Some((start, end.min(size.saturating_sub(1)))),
+        // bytes=500-
+        (Ok(start), Err(_)) if end.is_empty() => Some((start, size.saturating_sub(1))),
+        // bytes=-500
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            Some((size.saturating_sub(suffix), size.saturating_sub(1)))
+        }
+        _ => None,
+    };
+
+    match bounds {
+        Some((start, end)) if start < size => ByteRange::Partial(start, end),
+        Some(_) => ByteRange::Unsatisfiable,
+        None => ByteRange::Full,
+    }
+}
+
+// Build the ETag of a file based on its size and modification time
+fn build_etag(size: u64, modified: Option<SystemTime>) -> String {
+    let timestamp = modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    format!("\"{:x}-{:x}\"", timestamp, size)
+}
+
+// Check the conditional headers to know if the client has the latest
+// version of the file. If-None-Match takes precedence over If-Modified-Since.
+fn is_not_modified(req: &HttpRequest, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = header_str(req, header::IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*");
+    }
+
+    match (header_date(req, header::IF_MODIFIED_SINCE), modified) {
+        (Some(since), Some(modified)) => truncate_secs(modified) <= since,
+        _ => false,
+    }
+}
+
+// Check the If-Range header. The range only applies when the file didn't change
+fn if_range_matches(req: &HttpRequest, etag: &str, modified: Option<SystemTime>) -> bool {
+    match header_str(req, header::IF_RANGE) {
+        Some(value) if value.starts_with('"') => value == etag,
+        Some(_) => match (header_date(req, header::IF_RANGE), modified) {
+            (Some(date), Some(modified)) => truncate_secs(modified) <= date,
+            _ => false,
+        },
+        None => true,
+    }
+}
+
+// HTTP dates don't include subseconds, so they are removed before comparing
+fn truncate_secs(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => UNIX_EPOCH + std::time::Duration::from_secs(duration.as_secs()),
+        Err(_) => time,
+    }
+}
+
+// Retrieve a header value as a string
+fn header_str(req: &HttpRequest, name: header::HeaderName) -> Option<&str> {
+    req.headers().get(name)?.to_str().ok()
+}
+
+// Retrieve a header value as a date
+fn header_date(req: &HttpRequest, name: header::HeaderName) -> Option<SystemTime> {
+    header_str(req, name)?
+        .parse::<header::HttpDate>()
+        .ok()
+        .map(SystemTime::from)
+}
+
+// Read length bytes from the file, starting at the given position
+fn read_range(path: &Path, start: u64, length: u64) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut contents = Vec::with_capacity(length as usize);
+
+    file.seek(SeekFrom::Start(start))?;
+    file.take(length).read_to_end(&mut contents)?;
+
+    Ok(contents)
+}
+
+// Calculate the MIME type of the file based on its extension
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "zip" => "application/zip",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_path_from_url() {
+        assert_eq!(relative_path("/"), Some(PathBuf::new()));
+        assert_eq!(
+            relative_path("/css/main.css"),
+            Some(PathBuf::from("css/main.css"))
+        );
+        assert_eq!(
+            relative_path("/images/my%20logo.png"),
+            Some(PathBuf::from("images/my logo.png"))
+        );
+        // Reject paths outside of the public folder
+        assert_eq!(relative_path("/../secret.txt"), None);
+        assert_eq!(relative_path("/css/%2e%2e/%2e%2e/secret.txt"), None);
+        assert_eq!(relative_path("/css/..%5csecret.txt"), None);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(parse_range("bytes=0-499", 1000), ByteRange::Partial(0, 499));
+        assert_eq!(
+            parse_range("bytes=500-", 1000),
+            ByteRange::Partial(500, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=-200", 1000),
+            ByteRange::Partial(800, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=900-1500", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(parse_range("bytes=-2000", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-0", 0), ByteRange::Unsatisfiable);
+        // Unsupported or invalid ranges return the entire file
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+        assert_eq!(parse_range("items=0-5", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=5-1", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=abc", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn accept_encoding_parsing() {
+        assert_eq!(
+            accepted_encodings("gzip, deflate, br"),
+            vec!["gzip", "deflate", "br"]
+        );
+        assert_eq!(accepted_encodings("br;q=1.0, gzip;q=0"), vec!["br"]);
+        assert_eq!(accepted_encodings(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!(
+            content_type(Path::new("index.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("app.JS")),
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(content_type(Path::new("logo.svg")), "image/svg+xml");
+        assert_eq!(content_type(Path::new("module.wasm")), "application/wasm");
+        assert_eq!(
+            content_type(Path::new("LICENSE")),
+            "application/octet-stream"
+        );
+    }
+}
@@ -2,11 +2,24 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::data::kv::KVConfigData;
+use crate::router::{ConflictStrategy, IgnoreRules};
+use crate::runner::Protocol;
+use actix_web::http::{
+    header::{HeaderName, HeaderValue},
+    Method,
+};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use toml::from_slice;
 
+/// Name of the project configuration file. It's loaded from the root folder
+pub const PROJECT_CONFIG_FILE: &str = "wws.toml";
+
+/// Default folder for the compiled modules, relative to the project folder
+pub const DEFAULT_CACHE_FOLDER: &str = ".wws/cache";
+
 /// Handlers configuration. These files are optional when no configuration change is required.
 #[derive(Deserialize, Clone)]
 pub struct Config {
@@ -14,8 +27,39 @@ pub struct Config {
     pub name: Option<String>,
     /// Mandatory version of the file
     pub version: String,
+    /// Protocol version of the module when it doesn't declare one in its
+    /// custom section
+    pub protocol: Option<String>,
+    /// HTTP methods the handler replies to. By default, it replies to all of them
+    pub methods: Option<Vec<String>>,
     /// Optional data configuration
     pub data: Option<ConfigData>,
+    /// Optional execution limits. The project configuration provides the default values
+    pub limits: Option<LimitsConfig>,
+}
+
+/// Execution limits for the handlers
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Maximum time to reply in milliseconds
+    pub timeout: Option<u64>,
+    /// Maximum amount of fuel to consume. Roughly, every Wasm instruction consumes one unit
+    pub fuel: Option<u64>,
+    /// Maximum size of a linear memory in megabytes
+    pub memory: Option<u64>,
+    /// Maximum number of elements of a table
+    pub table_elements: Option<u32>,
+    /// Maximum number of instances
+    pub instances: Option<usize>,
+    /// Maximum number of tables
+    pub tables: Option<usize>,
+    /// Maximum number of linear memories
+    pub memories: Option<usize>,
+    /// Maximum number of requests the handler runs at the same time
+    pub concurrency: Option<usize>,
+    /// Maximum number of requests waiting to run when the handler reaches its concurrency
+    pub queue: Option<usize>,
 }
 
 /// Configure a data plugin for the handler
@@ -32,9 +76,14 @@ impl Config {
     ///
     /// # Examples
     ///
-    /// ```
+    /// ```toml
     /// name = "todos"
     /// version = "1"
+    /// methods = ["GET", "POST"]
+    ///
+    /// [limits]
+    /// timeout = 5000
+    /// memory = 64
     ///
     /// [data]
     ///
@@ -56,6 +105,42 @@ impl Config {
         }
     }
 
+    /// Returns the HTTP methods the handler replies to if available. Invalid
+    /// methods are ignored
+    pub fn methods(&self) -> Option<Vec<Method>> {
+        let methods = self.methods.as_ref()?;
+        let mut parsed = Vec::new();
+
+        for method in methods.iter() {
+            match Method::from_bytes(method.to_uppercase().as_bytes()) {
+                Ok(m) => parsed.push(m),
+                Err(_) => eprintln!("Invalid HTTP method in the configuration: {}", method),
+            }
+        }
+
+        if parsed.is_empty() {
+            None
+        } else {
+            Some(parsed)
+        }
+    }
+
+    /// Returns the protocol version of the handler if it's configured.
+    /// Unsupported versions are ignored
+    pub fn protocol(&self) -> Option<Protocol> {
+        let version = self.protocol.as_ref()?;
+        let protocol = Protocol::parse(version);
+
+        if protocol.is_none() {
+            eprintln!(
+                "⚠️  Unsupported protocol version in the configuration: {}",
+                version
+            );
+        }
+
+        protocol
+    }
+
     /// Returns a data Key/Value configuration if available
     pub fn data_kv_config(&self) -> Option<&KVConfigData> {
         self.data.as_ref()?.kv.as_ref()
@@ -66,3 +151,448 @@ impl Config {
         Some(self.data_kv_config()?.namespace.clone())
     }
 }
+
+/// Project configuration. It's loaded from the `wws.toml` file in the root folder
+/// and it's optional. The flags in the command line take precedence over the values
+/// in this file.
+///
+/// # Examples
+///
+/// ```toml
+/// ignore = ["scripts/**"]
+///
+/// [server]
+/// host = "0.0.0.0"
+/// port = 3000
+/// route_conflicts = "precedence"
+/// concurrency = 16
+///
+/// [headers]
+/// "X-Frame-Options" = "DENY"
+///
+/// [limits]
+/// timeout = 10000
+/// fuel = 500000000
+///
+/// [engine]
+/// opt_level = "speed"
+///
+/// [data.kv]
+/// default = "global"
+/// namespaces = ["sessions"]
+///
+/// [routes."/api/users"]
+/// methods = ["GET", "POST"]
+/// headers = { "Cache-Control" = "no-store" }
+/// kv = "users"
+/// ```
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Server settings
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// Headers to add to every response. Handlers can override them
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Default execution limits for the handlers
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Settings of the Wasm engine that compiles and runs the handlers
+    #[serde(default)]
+    pub engine: EngineConfig,
+    /// Data plugins configuration
+    #[serde(default)]
+    pub data: ProjectData,
+    /// Gitignore-style patterns of the files that shouldn't become routes
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Changes to the configuration of specific routes. The key is the route path
+    #[serde(default)]
+    pub routes: HashMap<String, RouteOverride>,
+}
+
+/// Server settings
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Hostname to initiate the server
+    pub host: Option<String>,
+    /// Port to initiate the server
+    pub port: Option<u16>,
+    /// Behavior when multiple handlers reply to the same route
+    pub route_conflicts: Option<ConflictStrategy>,
+    /// Maximum number of modules running at the same time. By default,
+    /// it's the number of available CPUs
+    pub concurrency: Option<usize>,
+    /// Maximum number of requests waiting to run when the server reaches its concurrency
+    pub queue: Option<usize>,
+    /// Read the scheme and the host of the requests from the `Forwarded` and
+    /// `X-Forwarded-*` headers. Only enable it behind a proxy that sets them
+    pub trusted_proxy: Option<bool>,
+}
+
+/// Settings of the Wasm engine. They apply to all the handlers and they
+/// require to restart the server
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Optimizations to apply when compiling the modules
+    pub opt_level: Option<OptLevel>,
+    /// Compile the functions of a module in parallel
+    pub parallel_compilation: Option<bool>,
+    /// Generate the debug information of the modules for native debuggers
+    pub debug_info: Option<bool>,
+    /// Allocate the instances from a pool. It's faster under load, but it
+    /// reserves the memory of every slot when the server starts
+    pub pooling: Option<bool>,
+    /// Store the compiled modules to start faster. It's enabled by default
+    pub cache: Option<bool>,
+    /// Folder for the compiled modules. Relative paths start in the project folder
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl EngineConfig {
+    /// Returns the folder for the compiled modules if the cache is enabled
+    pub fn cache_folder(&self, base_path: &Path) -> Option<PathBuf> {
+        if self.cache == Some(false) {
+            return None;
+        }
+
+        match &self.cache_dir {
+            Some(folder) => Some(base_path.join(folder)),
+            None => Some(base_path.join(DEFAULT_CACHE_FOLDER)),
+        }
+    }
+}
+
+/// Optimization level of the compiled modules
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum OptLevel {
+    /// No optimizations. Modules compile faster, but they run slower
+    None,
+    /// Optimize for speed. It's the default
+    Speed,
+    /// Optimize for speed and size
+    SpeedAndSize,
+}
+
+/// Data plugins configuration for the project
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectData {
+    /// Key/Value store settings
+    #[serde(default)]
+    pub kv: ProjectKVConfig,
+}
+
+/// Key/Value store settings for the project
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectKVConfig {
+    /// Namespace for the handlers that don't configure one
+    pub default: Option<String>,
+    /// Namespaces to create when the server starts
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+}
+
+/// Changes to the configuration of a route. They take precedence over
+/// the handler configuration file
+#[derive(Deserialize, Clone, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RouteOverride {
+    /// HTTP methods the route replies to
+    pub methods: Option<Vec<String>>,
+    /// Headers to add to the route responses
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// The Key/Value namespace the route will access
+    pub kv: Option<String>,
+}
+
+impl ProjectConfig {
+    /// Load the project configuration. The path points to a specific file. When it's
+    /// not present, it looks for the `wws.toml` file in the base path. If there's
+    /// no file, it returns the default configuration.
+    pub fn load(base_path: &Path, path: Option<&PathBuf>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.clone(),
+            None => {
+                let path = base_path.join(PROJECT_CONFIG_FILE);
+
+                if !path.exists() {
+                    return Ok(Self::default());
+                }
+
+                path
+            }
+        };
+
+        let contents = fs::read(&path).map_err(|err| {
+            format!(
+                "Error reading the project configuration at {}: {}",
+                path.display(),
+                err
+            )
+        })?;
+
+        Self::from_slice(&contents).map_err(|err| {
+            format!(
+                "Invalid project configuration at {}:{}",
+                path.display(),
+                err
+            )
+        })
+    }
+
+    // Parse and validate the configuration
+    fn from_slice(contents: &[u8]) -> Result<Self, String> {
+        let config: ProjectConfig = from_slice(contents).map_err(|err| format!(" {}", err))?;
+        let errors = config.validate();
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(format!("\n    - {}", errors.join("\n    - ")))
+        }
+    }
+
+    /// Check the values that TOML parsing can't validate. It returns
+    /// a description for every invalid value
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.server.port == Some(0) {
+            errors.push(String::from("server.port must be greater than 0"));
+        }
+
+        if let Some(host) = &self.server.host {
+            if host.trim().is_empty() {
+                errors.push(String::from("server.host can't be empty"));
+            }
+        }
+
+        if self.limits.timeout == Some(0) {
+            errors.push(String::from("limits.timeout must be greater than 0"));
+        }
+
+        if self.limits.fuel == Some(0) {
+            errors.push(String::from("limits.fuel must be greater than 0"));
+        }
+
+        if self.limits.memory == Some(0) {
+            errors.push(String::from("limits.memory must be greater than 0"));
+        }
+
+        if self.server.concurrency == Some(0) {
+            errors.push(String::from("server.concurrency must be greater than 0"));
+        }
+
+        if self.limits.concurrency == Some(0) {
+            errors.push(String::from("limits.concurrency must be greater than 0"));
+        }
+
+        validate_headers("headers", &self.headers, &mut errors);
+
+        let mut ignore = IgnoreRules::default();
+        for pattern in self.ignore.iter() {
+            if let Err(err) = ignore.add(pattern) {
+                errors.push(format!("ignore: {}", err));
+            }
+        }
+
+        let namespaces = self
+            .data
+            .kv
+            .default
+            .iter()
+            .chain(self.data.kv.namespaces.iter())
+            .chain(self.routes.values().filter_map(|route| route.kv.as_ref()));
+
+        for namespace in namespaces {
+            if namespace.trim().is_empty() {
+                errors.push(String::from("data.kv: namespaces can't be empty"));
+            }
+        }
+
+        for (path, route) in self.routes.iter() {
+            if !path.starts_with('/') {
+                errors.push(format!(
+                    "routes.\"{}\": route paths must start with /",
+                    path
+                ));
+            }
+
+            for method in route.methods.iter().flatten() {
+                if Method::from_bytes(method.to_uppercase().as_bytes()).is_err() {
+                    errors.push(format!(
+                        "routes.\"{}\".methods: invalid HTTP method \"{}\"",
+                        path, method
+                    ));
+                }
+            }
+
+            validate_headers(
+                &format!("routes.\"{}\".headers", path),
+                &route.headers,
+                &mut errors,
+            );
+        }
+
+        errors
+    }
+
+    /// Returns the changes for the given route path if available
+    pub fn route(&self, path: &str) -> Option<&RouteOverride> {
+        self.routes.get(path)
+    }
+}
+
+impl RouteOverride {
+    /// Returns the HTTP methods if available. The values are validated
+    /// when loading the configuration
+    pub fn methods(&self) -> Option<Vec<Method>> {
+        let methods = self.methods.as_ref()?;
+
+        Some(
+            methods
+                .iter()
+                .filter_map(|method| Method::from_bytes(method.to_uppercase().as_bytes()).ok())
+                .collect(),
+        )
+    }
+}
+
+// Check the given headers have a valid name and value
+fn validate_headers(field: &str, headers: &HashMap<String, String>, errors: &mut Vec<String>) {
+    for (name, value) in headers.iter() {
+        if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            errors.push(format!("{}: invalid header name \"{}\"", field, name));
+        } else if HeaderValue::from_str(value).is_err() {
+            errors.push(format!(
+                "{}: invalid value for the \"{}\" header",
+                field, name
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_protocol() {
+        let config: Config = toml::from_str(r#"version = "1""#).unwrap();
+        assert_eq!(config.protocol(), None);
+
+        let config: Config = toml::from_str(
+            r#"
+            version = "1"
+            protocol = "2"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.protocol(), Some(Protocol::V2));
+
+        let config: Config = toml::from_str(
+            r#"
+            version = "1"
+            protocol = "99"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.protocol(), None);
+    }
+
+    #[test]
+    fn project_config_parsing() {
+        let config = ProjectConfig::from_slice(
+            br#"
+            ignore = ["scripts/**"]
+
+            [server]
+            host = "0.0.0.0"
+            port = 3000
+            route_conflicts = "precedence"
+            trusted_proxy = true
+
+            [headers]
+            "X-Frame-Options" = "DENY"
+
+            [limits]
+            timeout = 5000
+
+            [engine]
+            opt_level = "speed_and_size"
+
+            [data.kv]
+            default = "global"
+            namespaces = ["sessions"]
+
+            [routes."/api/users"]
+            methods = ["get", "POST"]
+            kv = "users"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.server.host, Some(String::from("0.0.0.0")));
+        assert_eq!(config.server.port, Some(3000));
+        assert_eq!(
+            config.server.route_conflicts,
+            Some(ConflictStrategy::Precedence)
+        );
+        assert_eq!(config.server.trusted_proxy, Some(true));
+        assert_eq!(config.headers.len(), 1);
+        assert_eq!(config.limits.timeout, Some(5000));
+        assert_eq!(config.engine.opt_level, Some(OptLevel::SpeedAndSize));
+        assert_eq!(config.data.kv.default, Some(String::from("global")));
+        assert_eq!(config.ignore, vec![String::from("scripts/**")]);
+        assert_eq!(
+            config.route("/api/users").and_then(|r| r.methods()),
+            Some(vec![Method::GET, Method::POST])
+        );
+        assert!(config.route("/api").is_none());
+    }
+
+    #[test]
+    fn project_config_defaults() {
+        let config = ProjectConfig::from_slice(b"").unwrap();
+
+        assert!(config.server.port.is_none());
+        assert!(config.headers.is_empty());
+        assert!(config.routes.is_empty());
+    }
+
+    #[test]
+    fn project_config_validation() {
+        let check_error = |contents: &str, expected: &str| {
+            let err = ProjectConfig::from_slice(contents.as_bytes()).unwrap_err();
+            assert!(
+                err.contains(expected),
+                "{} doesn't contain {}",
+                err,
+                expected
+            );
+        };
+
+        check_error("[server]\nport = 0", "server.port must be greater than 0");
+        check_error("[server]\nport = 70000", "port");
+        check_error("[server]\nhots = \"a\"", "unknown field `hots`");
+        check_error("[server]\nroute_conflicts = \"ignore\"", "unknown variant");
+        check_error("[headers]\n\"Bad Header\" = \"1\"", "invalid header name");
+        check_error(
+            "[limits]\ntimeout = 0",
+            "limits.timeout must be greater than 0",
+        );
+        check_error("ignore = [\"[\"]", "ignore: invalid pattern");
+        check_error("[routes.api]\nkv = \"a\"", "must start with /");
+        check_error(
+            "[routes.\"/api\"]\nmethods = [\"GE T\"]",
+            "invalid HTTP method",
+        );
+    }
+}
@@ -13,6 +13,7 @@ pub struct KVConfigData {
 
 /// An in-memory Key/Value store. It contains multiple namespaces which has their
 /// own K/V store inside. This is used to scope the data handlers can access
+#[derive(Default)]
 pub struct KV {
     /// The available K/V stores
     pub stores: HashMap<String, KVStore>,
@@ -74,6 +75,7 @@ impl KVStore {
     }
 
     /// Clone the current content of the Key/Value store
+    #[allow(clippy::should_implement_trait)]
     pub fn clone(&self) -> HashMap<String, String> {
         self.store.clone()
     }
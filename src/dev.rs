@@ -0,0 +1,238 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use actix_web::{
+    body::{self, BoxBody},
+    http::{header, StatusCode},
+    web::Bytes,
+    HttpResponse,
+};
+use futures_util::stream;
+use std::convert::Infallible;
+use std::sync::{Mutex, RwLock};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+/// URL path to subscribe to the live-reload events. Browsers connect to it
+/// using Server-Sent Events
+pub const LIVE_RELOAD_PATH: &str = "/_wws/live-reload";
+
+/// Script that reloads the page when the project changes
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(() => {
+  const source = new EventSource("/_wws/live-reload");
+  source.addEventListener("reload", () => window.location.reload());
+})();
+</script>"#;
+
+/// Development mode. It keeps track of the connected browsers to reload them
+/// when the project changes, and the last error loading the project to show it
+/// as an overlay page.
+#[derive(Default)]
+pub struct DevServer {
+    /// Connected browsers
+    clients: Mutex<Vec<UnboundedSender<Bytes>>>,
+    /// Last error loading the project if any
+    error: RwLock<Option<String>>,
+}
+
+impl DevServer {
+    /// Reply with a stream of Server-Sent Events. It sends a `reload` event
+    /// every time the project changes
+    pub fn subscribe(&self) -> HttpResponse {
+        let (sender, receiver) = unbounded_channel();
+        // Flush the headers so the browser knows it's connected
+        let _ = sender.send(Bytes::from_static(b": connected\n\n"));
+        self.clients.lock().unwrap().push(sender);
+
+        let events = stream::unfold(receiver, |mut receiver| async move {
+            receiver
+                .recv()
+                .await
+                .map(|event| (Ok::<Bytes, Infallible>(event), receiver))
+        });
+
+        HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .streaming(events)
+    }
+
+    /// Reload the connected browsers. Closed connections are removed
+    pub fn notify_reload(&self) {
+        self.clients.lock().unwrap().retain(|client| {
+            client
+                .send(Bytes::from_static(b"event: reload\ndata:\n\n"))
+                .is_ok()
+        });
+    }
+
+    /// Set the last error loading the project. None means the project is valid
+    pub fn set_error(&self, error: Option<String>) {
+        *self.error.write().unwrap() = error;
+    }
+
+    /// Reply with an overlay page if the project has an error
+    pub fn error_response(&self) -> Option<HttpResponse> {
+        let error = self.error.read().unwrap();
+
+        error.as_ref().map(|error| {
+            HttpResponse::InternalServerError()
+                .content_type("text/html; charset=utf-8")
+                .body(error_page(error))
+        })
+    }
+
+    /// Add the live-reload script to HTML responses. Compressed and partial
+    /// responses are kept as they are. It replies with an error page when
+    /// the body can't be read.
+    pub async fn inject_script(&self, response: HttpResponse) -> HttpResponse {
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.starts_with("text/html"))
+            .unwrap_or(false);
+
+        if !is_html
+            || response.status() == StatusCode::PARTIAL_CONTENT
+            || response.headers().contains_key(header::CONTENT_ENCODING)
+        {
+            return response;
+        }
+
+        let (mut response, body) = response.into_parts();
+        // The body changes, so these headers are not valid anymore
+        response.headers_mut().remove(header::CONTENT_LENGTH);
+        response.headers_mut().remove(header::ETAG);
+
+        let html = match body::to_bytes(body).await {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(err) => {
+                return HttpResponse::InternalServerError()
+                    .content_type("text/html; charset=utf-8")
+                    .body(error_page(&format!(
+                        "Error reading the response body: {}",
+                        err
+                    )))
+            }
+        };
+
+        response
+            .set_body(BoxBody::new(inject(&html)))
+            .map_into_boxed_body()
+    }
+}
+
+// Add the live-reload script before the closing body tag. If there's no tag,
+// the script goes at the end of the document
+fn inject(html: &str) -> String {
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(position) => format!(
+            "{}{}{}",
+            &html[..position],
+            LIVE_RELOAD_SCRIPT,
+            &html[position..]
+        ),
+        None => format!("{}{}", html, LIVE_RELOAD_SCRIPT),
+    }
+}
+
+// Build the overlay page that shows an error loading the project. It reloads
+// when the project changes, so it disappears after fixing the error
+fn error_page(error: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>Error loading the project</title>
+  <style>
+    body {{ margin: 0; background: rgba(0, 0, 0, 0.85); color: #f8f8f8; font-family: sans-serif; }}
+    main {{ max-width: 960px; margin: 48px auto; padding: 24px; background: #1e1e1e; border-top: 4px solid #e83b46; }}
+    pre {{ white-space: pre-wrap; color: #ff8a8a; font-size: 14px; }}
+  </style>
+</head>
+<body>
+  <main>
+    <h1>Error loading the project</h1>
+    <pre>{}</pre>
+    <p>Fix the error and save the file. This page reloads automatically.</p>
+  </main>
+  {}
+</body>
+</html>"#,
+        escape_html(error),
+        LIVE_RELOAD_SCRIPT
+    )
+}
+
+// Escape the HTML special characters
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_reload_script_injection() {
+        assert_eq!(
+            inject("<html><body><h1>Hi</h1></body></html>"),
+            format!(
+                "<html><body><h1>Hi</h1>{}</body></html>",
+                LIVE_RELOAD_SCRIPT
+            )
+        );
+        assert_eq!(
+            inject("<BODY>Hi</BODY>"),
+            format!("<BODY>Hi{}</BODY>", LIVE_RELOAD_SCRIPT)
+        );
+        assert_eq!(
+            inject("<p>Hi</p>"),
+            format!("<p>Hi</p>{}", LIVE_RELOAD_SCRIPT)
+        );
+    }
+
+    #[test]
+    fn error_page_escaping() {
+        let page = error_page("Error loading <api>.wasm: \"invalid\" & broken");
+
+        assert!(page.contains("Error loading &lt;api&gt;.wasm: &quot;invalid&quot; &amp; broken"));
+        assert!(page.contains(LIVE_RELOAD_SCRIPT));
+    }
+
+    #[actix_web::test]
+    async fn live_reload_body_errors() {
+        let failing = stream::once(async {
+            Err::<Bytes, _>(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "connection reset",
+            ))
+        });
+        let response = HttpResponse::Ok()
+            .content_type("text/html")
+            .streaming(failing);
+
+        let response = DevServer::default().inject_script(response).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let page = body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&page).contains("connection reset"));
+    }
+
+    #[test]
+    fn overlay_only_with_errors() {
+        let dev = DevServer::default();
+        assert!(dev.error_response().is_none());
+
+        dev.set_error(Some(String::from("Invalid module")));
+        assert_eq!(
+            dev.error_response().map(|res| res.status()),
+            Some(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+    }
+}
@@ -0,0 +1,13 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The modules of the wws server. The `wws` binary runs the HTTP server on
+//! top of them, and the benchmarks use them directly.
+
+pub mod assets;
+pub mod config;
+pub mod data;
+pub mod dev;
+pub mod router;
+pub mod runner;
+pub mod watcher;
@@ -1,140 +1,472 @@
 // Copyright 2022 VMware, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-mod config;
-mod data;
-mod router;
-mod runner;
-
 use actix_web::{
-    http::StatusCode,
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        StatusCode,
+    },
     middleware,
     web::{self, Bytes, Data},
     App, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use clap::Parser;
+use assets::StaticAssets;
+use clap::{Parser, Subcommand};
+use config::ProjectConfig;
 use data::kv::KV;
-use runner::WasmOutput;
-use std::path::PathBuf;
-use std::{collections::HashMap, sync::RwLock};
+use dev::DevServer;
+use router::{ConflictStrategy, RouteKind, RouteMatch, RouteParams};
+use runner::{
+    ConcurrencyLimit, LimitExceeded, Limits, MiddlewareOutput, PoolSize, RequestChanges, Runner,
+    Runtime, Saturated, TrustedProxy, WasmError, WasmOutput,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use wasm_workers_server::{assets, config, data, dev, router, runner, watcher};
+use watcher::Watcher;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 8080;
+
+// URL path of the runtime metrics
+const METRICS_PATH: &str = "/_wws/metrics";
 
 // Arguments
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Hostname to initiate the server
-    #[clap(long = "host", default_value = "127.0.0.1")]
-    hostname: String,
+    /// Hostname to initiate the server [default: 127.0.0.1]
+    #[clap(long = "host", global = true)]
+    hostname: Option<String>,
 
-    /// Port to initiate the server
-    #[clap(short, long, default_value_t = 8080)]
-    port: u16,
+    /// Port to initiate the server [default: 8080]
+    #[clap(short, long, global = true)]
+    port: Option<u16>,
 
     /// Folder to read WebAssembly modules from
     #[clap(value_parser, default_value = ".")]
     path: PathBuf,
+
+    /// Behavior when multiple handlers reply to the same route [default: fail]
+    #[clap(long = "route-conflicts", value_enum, global = true)]
+    route_conflicts: Option<ConflictStrategy>,
+
+    /// Project configuration file [default: <PATH>/wws.toml]
+    #[clap(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Show more details about the project, like the ignored files
+    #[clap(short, long, global = true)]
+    verbose: bool,
+
+    /// Reload the handlers and configuration files when they change
+    #[clap(short, long, global = true)]
+    watch: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-// Common structures
+impl Args {
+    // Parse the arguments. The folder of the subcommands replaces the main one
+    fn load() -> Self {
+        let mut args = Self::parse();
+
+        if let Some(Command::Dev { path: Some(path) }) = &args.command {
+            args.path = path.clone();
+        }
+
+        args
+    }
+
+    // Check if the server runs in development mode
+    fn dev(&self) -> bool {
+        matches!(self.command, Some(Command::Dev { .. }))
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Development mode. It reloads the browser when the project changes and shows
+    /// the errors as an overlay page. It implies --watch
+    Dev {
+        /// Folder to read WebAssembly modules from [default: .]
+        #[clap(value_parser)]
+        path: Option<PathBuf>,
+    },
+}
+
+// Common structures. The route table is replaced when the project changes, so
+// every request keeps a reference to the table that was available when it started.
+// The runtime compiles the modules of every table.
 struct Routes {
-    routes: Vec<router::Route>,
+    table: RwLock<Arc<router::RouteTable>>,
+    runtime: Runtime,
+}
+
+impl Routes {
+    fn new(runtime: Runtime, table: router::RouteTable) -> Self {
+        Self {
+            table: RwLock::new(Arc::new(table)),
+            runtime,
+        }
+    }
+
+    // Retrieve the current route table
+    fn table(&self) -> Arc<router::RouteTable> {
+        Arc::clone(&self.table.read().unwrap())
+    }
+
+    // Replace the route table. Running requests finish with the previous one
+    fn replace(&self, table: router::RouteTable) {
+        *self.table.write().unwrap() = Arc::new(table);
+    }
 }
 
 struct DataConnectors {
     kv: KV,
 }
 
-async fn wasm_handler(req: HttpRequest, body: Bytes) -> HttpResponse {
-    let routes = req.app_data::<Data<Routes>>().unwrap();
-    let data_connectors = req
-        .app_data::<Data<RwLock<DataConnectors>>>()
-        .unwrap()
-        .clone();
-    // We will improve error handling
-    let mut result: HttpResponse = HttpResponse::ServiceUnavailable().body("Error");
-
-    for route in routes.routes.iter() {
-        if route.path == req.path() {
-            let body_str = String::from_utf8(body.to_vec()).unwrap_or(String::from(""));
-
-            // Init KV
-            let kv_namespace = match &route.config {
-                Some(config) => config.data_kv_namespace(),
-                None => None,
-            };
+// Read the K/V store of the route if it's configured. It returns the namespace
+// and the current state
+fn read_kv(
+    req: &HttpRequest,
+    route: &router::Route,
+) -> (Option<String>, Option<HashMap<String, String>>) {
+    let data_connectors = req.app_data::<Data<RwLock<DataConnectors>>>().unwrap();
 
-            let store = match &kv_namespace {
-                Some(namespace) => {
-                    let connector = data_connectors.read().unwrap();
-                    let kv_store = connector.kv.find_store(namespace);
+    let kv_namespace = route.kv_namespace.clone();
 
-                    match kv_store {
-                        Some(store) => Some(store.clone()),
-                        None => None,
-                    }
+    let store = match &kv_namespace {
+        Some(namespace) => {
+            let connector = data_connectors.read().unwrap();
+            let kv_store = connector.kv.find_store(namespace);
+
+            kv_store.map(|store| store.clone())
+        }
+        None => None,
+    };
+
+    (kv_namespace, store)
+}
+
+// Write the new state of the K/V store if the route has one
+fn write_kv(req: &HttpRequest, kv_namespace: Option<String>, state: HashMap<String, String>) {
+    if let Some(namespace) = kv_namespace {
+        req.app_data::<Data<RwLock<DataConnectors>>>()
+            .unwrap()
+            .write()
+            .unwrap()
+            .kv
+            .replace_store(&namespace, state)
+    }
+}
+
+// Build the HTTP response from the output of a module. Failures keep
+// their status unless the handler sets a different one. The route headers
+// come from the project configuration and handlers can override them.
+// Repeated headers keep all their values and invalid ones are skipped.
+// It fails when the module returns an invalid binary body.
+fn build_response(
+    route: &router::Route,
+    output: &WasmOutput,
+    default_status: Option<u16>,
+) -> anyhow::Result<HttpResponse> {
+    let status = match default_status {
+        Some(status) if output.status == StatusCode::OK.as_u16() => status,
+        _ => output.status,
+    };
+
+    let mut builder = HttpResponse::build(StatusCode::from_u16(status).unwrap_or(StatusCode::OK));
+    // Default content type
+    builder.insert_header(("Content-Type", "text/html"));
+
+    for (key, val) in route.headers.iter() {
+        builder.insert_header((key.as_str(), val.as_str()));
+    }
+
+    for (key, values) in output.headers.iter() {
+        let name = match HeaderName::from_bytes(key.as_bytes()) {
+            Ok(name) => name,
+            Err(_) => {
+                eprintln!("⚠️  Skipping the invalid header name \"{}\"", key);
+                continue;
+            }
+        };
+
+        // The first value replaces the default and route headers
+        let mut first = true;
+        for value in values.iter() {
+            let value = match HeaderValue::from_str(value) {
+                Ok(value) => value,
+                Err(_) => {
+                    eprintln!("⚠️  Skipping an invalid value of the \"{}\" header", name);
+                    continue;
                 }
-                None => None,
             };
 
-            let handler_result = route
-                .runner
-                .run(&runner::build_wasm_input(&req, body_str, store))
-                .unwrap_or(WasmOutput {
-                    body: String::from("<p>There was an error running this function</p>"),
-                    headers: HashMap::from([("content-type".to_string(), "text/html".to_string())]),
-                    status: StatusCode::SERVICE_UNAVAILABLE.as_u16(),
-                    kv: HashMap::new(),
-                });
-
-            let mut builder = HttpResponse::build(
-                StatusCode::from_u16(handler_result.status).unwrap_or(StatusCode::OK),
-            );
-            // Default content type
-            builder.insert_header(("Content-Type", "text/html"));
-
-            for (key, val) in handler_result.headers.iter() {
-                // Note that QuickJS is replacing the "-" character
-                // with "_" on property keys. Here, we rollback it
-                builder.insert_header((key.replace("_", "-").as_str(), val.as_str()));
+            if first {
+                builder.insert_header((name.clone(), value));
+                first = false;
+            } else {
+                builder.append_header((name.clone(), value));
             }
+        }
+    }
+
+    Ok(builder.body(output.body()?))
+}
+
+// Run a module of the given route in the blocking pool, so it doesn't block the
+// server workers. It waits for a free slot in the route and the global concurrency
+// limits. If their queues are full, it fails with a Saturated error.
+async fn run_blocking<T, F>(req: &HttpRequest, route: &router::Route, run: F) -> anyhow::Result<T>
+where
+    F: FnOnce(&Runner, &Limits) -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    // The route slot comes first, so slow routes don't take all the global slots
+    let _route_permit = match &route.concurrency {
+        Some(limit) => Some(limit.acquire().await?),
+        None => None,
+    };
+    let _global_permit = match req.app_data::<Data<ConcurrencyLimit>>() {
+        Some(limit) => Some(limit.acquire().await?),
+        None => None,
+    };
+
+    let runner = route.runner.clone();
+    let limits = route.limits.clone();
 
-            // Write to the state if required
-            if kv_namespace.is_some() {
-                data_connectors
-                    .write()
-                    .unwrap()
-                    .kv
-                    .replace_store(&kv_namespace.unwrap(), handler_result.kv)
+    web::block(move || run(&runner, &limits)).await?
+}
+
+// Run the given route and build the HTTP response from its output. Not found
+// and error handlers also receive the details of the failure.
+async fn run_handler(
+    req: &HttpRequest,
+    route: &router::Route,
+    body: Bytes,
+    params: RouteParams,
+    error: Option<WasmError>,
+    changes: &RequestChanges,
+) -> anyhow::Result<HttpResponse> {
+    let (kv_namespace, store) = read_kv(req, route);
+    let default_status = error.as_ref().map(|e| e.status);
+
+    let protocol = route.runner.protocol();
+    let input = runner::build_wasm_input(req, &body, store, params, error, changes, protocol);
+    let handler_result =
+        run_blocking(req, route, move |runner, limits| runner.run(&input, limits)).await?;
+
+    let response = build_response(route, &handler_result, default_status)?;
+    write_kv(req, kv_namespace, handler_result.kv);
+
+    Ok(response)
+}
+
+// Run the middlewares that apply to the request. They run from the upper folders
+// to the deeper ones. It returns the changes for the request or the response
+// if a middleware replies to it.
+async fn run_middlewares(
+    req: &HttpRequest,
+    routes: &router::RouteTable,
+    body: &[u8],
+) -> Result<RequestChanges, HttpResponse> {
+    let mut changes = RequestChanges::default();
+
+    for (middleware, params) in routes.find_middlewares(req.path()) {
+        let (kv_namespace, store) = read_kv(req, middleware);
+        let protocol = middleware.runner.protocol();
+        let input = runner::build_wasm_input(req, body, store, params, None, &changes, protocol);
+
+        let output = run_blocking(req, middleware, move |runner, limits| {
+            runner.run_middleware(&input, limits)
+        })
+        .await;
+
+        match output {
+            Ok(MiddlewareOutput::Next { request, kv }) => {
+                if let Some(kv) = kv {
+                    write_kv(req, kv_namespace, kv);
+                }
+                changes.merge(request);
             }
+            Ok(MiddlewareOutput::Response(output)) => {
+                write_kv(req, kv_namespace, output.kv.clone());
 
-            result = builder.body(String::from(&handler_result.body))
+                return match build_response(middleware, &output, None) {
+                    Ok(response) => Err(response),
+                    Err(err) => {
+                        Err(run_error_handler(req, routes, req.path(), &err, &changes).await)
+                    }
+                };
+            }
+            Err(err) => {
+                return Err(run_error_handler(req, routes, req.path(), &err, &changes).await);
+            }
         }
     }
 
-    result
+    Ok(changes)
 }
 
-async fn debug(req: HttpRequest) -> impl Responder {
-    let value = req.app_data::<Data<Routes>>().unwrap();
-    HttpResponse::Ok().body(format!("Routes: {}", value.routes.len()))
+// Reply with the closest special handler of the given kind. If there's no handler
+// or it fails, it replies with the default response.
+async fn run_special_handler(
+    req: &HttpRequest,
+    routes: &router::RouteTable,
+    url_path: &str,
+    kind: RouteKind,
+    error: WasmError,
+    default_response: HttpResponse,
+    changes: &RequestChanges,
+) -> HttpResponse {
+    match routes.find_special(kind, url_path) {
+        Some((route, params)) => {
+            run_handler(req, route, Bytes::new(), params, Some(error), changes)
+                .await
+                .unwrap_or(default_response)
+        }
+        None => default_response,
+    }
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let args = Args::parse();
+// Reply to a handler failure. Handlers that reach their time limits reply with a
+// timeout status, and the ones that reach their memory limits reply with an internal
+// error. The error handler can customize the response. When the server is busy,
+// it replies directly to avoid running more modules.
+async fn run_error_handler(
+    req: &HttpRequest,
+    routes: &router::RouteTable,
+    url_path: &str,
+    err: &anyhow::Error,
+    changes: &RequestChanges,
+) -> HttpResponse {
+    if err.downcast_ref::<Saturated>().is_some() {
+        eprintln!("⚠️  {} ({})", err, url_path);
 
-    std::env::set_var("RUST_LOG", "actix_web=info");
-    env_logger::init();
+        return HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, runner::RETRY_AFTER))
+            .content_type("text/html")
+            .body("<p>The server is busy. Try it again later</p>");
+    }
 
-    println!("⚙️  Loading routes from: {}", &args.path.display());
-    let routes = Data::new(Routes {
-        routes: router::initialize_routes(&args.path),
-    });
+    let (status, message) = match err.downcast_ref::<LimitExceeded>() {
+        Some(limit) => {
+            eprintln!("⚠️  {} ({})", limit, url_path);
 
-    let data = Data::new(RwLock::new(DataConnectors { kv: KV::new() }));
+            match limit {
+                LimitExceeded::Timeout(_) | LimitExceeded::Fuel(_) => (
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "<p>The function took too long to reply</p>",
+                ),
+                LimitExceeded::Memory(_) | LimitExceeded::TableElements(_) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "<p>The function exceeded its memory limit</p>",
+                ),
+            }
+        }
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "<p>There was an error running this function</p>",
+        ),
+    };
+
+    run_special_handler(
+        req,
+        routes,
+        url_path,
+        RouteKind::Error,
+        WasmError::new(status, &err.to_string(), url_path),
+        HttpResponse::build(status)
+            .content_type("text/html")
+            .body(message),
+        changes,
+    )
+    .await
+}
+
+async fn wasm_handler(req: HttpRequest, body: Bytes) -> HttpResponse {
+    match req.app_data::<Data<DevServer>>() {
+        Some(dev) => match dev.error_response() {
+            Some(response) => response,
+            None => dev.inject_script(handle_request(&req, body).await).await,
+        },
+        None => handle_request(&req, body).await,
+    }
+}
+
+// Find the route for the request and run it. It runs the middlewares first
+// and falls back to the static assets and the not found handlers
+async fn handle_request(req: &HttpRequest, body: Bytes) -> HttpResponse {
+    let routes = req.app_data::<Data<Routes>>().unwrap().table();
+    let changes = match run_middlewares(req, &routes, &body).await {
+        Ok(changes) => changes,
+        Err(response) => return response,
+    };
+    // Middlewares may rewrite the URL
+    let url_path = changes.path().unwrap_or_else(|| String::from(req.path()));
+
+    let (route, params) = match routes.find(&url_path, req.method()) {
+        RouteMatch::Found(route, params) => (route, params),
+        RouteMatch::MethodNotAllowed(methods) => {
+            return HttpResponse::MethodNotAllowed()
+                .insert_header(header::Allow(methods))
+                .body("Method not allowed");
+        }
+        RouteMatch::NotFound => {
+            // Handlers take precedence over static assets
+            if let Some(assets) = req.app_data::<Data<StaticAssets>>() {
+                if let Some(response) = assets.serve(req, &url_path).await {
+                    return response;
+                }
+            }
 
+            return run_special_handler(
+                req,
+                &routes,
+                &url_path,
+                RouteKind::NotFound,
+                WasmError::new(StatusCode::NOT_FOUND, "Not found", &url_path),
+                HttpResponse::NotFound().body("Not found"),
+                &changes,
+            )
+            .await;
+        }
+    };
+
+    match run_handler(req, route, body, params, None, &changes).await {
+        Ok(response) => response,
+        Err(err) => run_error_handler(req, &routes, &url_path, &err, &changes).await,
+    }
+}
+
+// Stream the live-reload events to the browser. Only available in development mode
+async fn live_reload(req: HttpRequest) -> HttpResponse {
+    req.app_data::<Data<DevServer>>().unwrap().subscribe()
+}
+
+async fn debug(req: HttpRequest) -> impl Responder {
+    let value = req.app_data::<Data<Routes>>().unwrap();
+    HttpResponse::Ok().body(format!("Routes: {}", value.table().len()))
+}
+
+// Report the running instances and the pool utilization in the Prometheus format
+async fn metrics(req: HttpRequest) -> HttpResponse {
+    let routes = req.app_data::<Data<Routes>>().unwrap();
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(routes.runtime.metrics().render())
+}
+
+// Print the available routes
+fn print_routes(routes: &router::RouteTable, hostname: &str, port: u16) {
     println!("🗺  Detected routes:");
-    for route in routes.routes.iter() {
+    for route in routes.iter() {
         let default_name = String::from("default");
         let name = if let Some(config) = &route.config {
             config.name.as_ref().unwrap_or(&default_name)
@@ -142,43 +474,302 @@ async fn main() -> std::io::Result<()> {
             &default_name
         };
 
+        let methods = match &route.methods {
+            Some(methods) => format!(" [{}]", router::join_methods(methods)),
+            None => String::new(),
+        };
+        let description = match route.kind {
+            RouteKind::Handler => "",
+            RouteKind::NotFound => "Not found handler for ",
+            RouteKind::Error => "Error handler for ",
+            RouteKind::Middleware => "Middleware for ",
+        };
+
         println!(
-            "    - http://{}:{}{}\n      => {} (handler: {})",
-            &args.hostname,
-            args.port,
+            "    - {}http://{}:{}{}{}\n      => {} (handler: {})",
+            description,
+            hostname,
+            port,
             route.path,
+            methods,
             route.handler.display(),
             name
         );
     }
+}
+
+// Create the K/V stores for the project and the routes. Existing stores keep their data
+fn create_kv_stores(
+    data: &RwLock<DataConnectors>,
+    project: &ProjectConfig,
+    routes: &router::RouteTable,
+) {
+    let mut data = data.write().unwrap();
+
+    for namespace in project.data.kv.namespaces.iter() {
+        data.kv.create_store(namespace);
+    }
+
+    for route in routes.iter() {
+        if let Some(namespace) = &route.kv_namespace {
+            data.kv.create_store(namespace);
+        }
+    }
+}
+
+// Load the project again and replace the route table. The handlers that didn't
+// change keep their runners. If there's an error, the server keeps the previous routes.
+// Server settings and global headers require a restart.
+fn reload_routes(
+    args: &Args,
+    routes: &Routes,
+    data: &RwLock<DataConnectors>,
+) -> Result<usize, String> {
+    let project = ProjectConfig::load(&args.path, args.config.as_ref())?;
+    let route_conflicts = args
+        .route_conflicts
+        .or(project.server.route_conflicts)
+        .unwrap_or(ConflictStrategy::Fail);
+    let previous = routes.table();
+
+    let table = router::initialize_routes(
+        &routes.runtime,
+        &args.path,
+        &project,
+        route_conflicts,
+        args.verbose,
+        Some(&previous),
+    )?;
+    let count = table.len();
+
+    create_kv_stores(data, &project, &table);
+    routes.replace(table);
+
+    Ok(count)
+}
+
+// Check if the given file is a static asset. They don't require to reload the routes
+fn is_static_asset(base_path: &Path, path: &Path) -> bool {
+    path.strip_prefix(base_path)
+        .map(|relative| relative.starts_with(assets::PUBLIC_FOLDER))
+        .unwrap_or(false)
+}
+
+// Watch the project folder in a different thread and reload the routes on every change.
+// In development mode, it also reloads the browsers and keeps the last error to show it.
+fn watch_routes(
+    args: Args,
+    routes: Data<Routes>,
+    data: Data<RwLock<DataConnectors>>,
+    dev: Option<Data<DevServer>>,
+) {
+    thread::spawn(move || {
+        Watcher::new(&args.path).watch(|changes| {
+            if changes.iter().all(|path| is_static_asset(&args.path, path)) {
+                if let Some(dev) = &dev {
+                    dev.notify_reload();
+                }
+                return;
+            }
+
+            if args.verbose {
+                for path in changes.iter() {
+                    println!("📝 Changed: {}", path.display());
+                }
+            }
+
+            let error = match reload_routes(&args, &routes, &data) {
+                Ok(count) => {
+                    println!("🔄 Reloaded the project. Available routes: {}", count);
+                    None
+                }
+                Err(err) => {
+                    eprintln!("❌ {}\n   The server keeps the previous routes", err);
+                    Some(err)
+                }
+            };
+
+            if let Some(dev) = &dev {
+                dev.set_error(error);
+                dev.notify_reload();
+            }
+        });
+    });
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::load();
+
+    std::env::set_var("RUST_LOG", "actix_web=info");
+    env_logger::init();
+
+    let project = match ProjectConfig::load(&args.path, args.config.as_ref()) {
+        Ok(project) => project,
+        Err(err) => {
+            eprintln!("❌ {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Flags take precedence over the project configuration
+    let hostname = args
+        .hostname
+        .clone()
+        .or_else(|| project.server.host.clone())
+        .unwrap_or_else(|| String::from(DEFAULT_HOST));
+    let port = args.port.or(project.server.port).unwrap_or(DEFAULT_PORT);
+    let route_conflicts = args
+        .route_conflicts
+        .or(project.server.route_conflicts)
+        .unwrap_or(ConflictStrategy::Fail);
+
+    let dev = args.dev().then(|| Data::new(DevServer::default()));
+
+    // By default, run as many modules at the same time as available cores
+    let mut concurrency = project.server.concurrency.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(1)
+    });
+
+    let handlers = router::find_handlers(&args.path, &project, false).unwrap_or_default();
+    let handler_limits = router::handler_limits(&handlers);
+
+    // Counting the fuel slows down every module, so the engine only does it
+    // when there's a fuel limit
+    let fuel =
+        project.limits.fuel.is_some() || handler_limits.iter().any(|limits| limits.fuel.is_some());
+
+    // The pool has a slot for every module that may run at the same time
+    let pool = if project.engine.pooling == Some(true) {
+        let pool = PoolSize::new(
+            handlers.len(),
+            concurrency,
+            &project.limits,
+            &handler_limits,
+        );
+        println!(
+            "🏊 Allocating the instances from a pool of {} ({}MB of memory each)",
+            pool.instances,
+            pool.memory_megabytes()
+        );
+
+        // New routes after a reload can't run more modules than the pool allows
+        concurrency = pool.instances;
+        Some(pool)
+    } else {
+        None
+    };
+
+    let concurrency_limit = Data::new(ConcurrencyLimit::new(
+        concurrency,
+        project.server.queue.unwrap_or(runner::DEFAULT_QUEUE),
+    ));
+
+    println!("⚙️  Loading routes from: {}", &args.path.display());
+    let runtime = match Runtime::new(&project.engine, &args.path, pool, fuel) {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("❌ Error initializing the Wasm engine: {}", err);
+            std::process::exit(1);
+        }
+    };
+    runner::start_epoch_ticker(runtime.engine().clone());
+
+    let routes = match router::initialize_routes(
+        &runtime,
+        &args.path,
+        &project,
+        route_conflicts,
+        args.verbose,
+        None,
+    ) {
+        Ok(routes) => Data::new(Routes::new(runtime, routes)),
+        Err(err) => {
+            eprintln!("❌ {}", err);
+
+            // In development mode, the server starts to show the error in the browser
+            match &dev {
+                Some(dev) => {
+                    dev.set_error(Some(err));
+                    Data::new(Routes::new(runtime, router::RouteTable::new(Vec::new())))
+                }
+                None => std::process::exit(1),
+            }
+        }
+    };
+
+    let data = Data::new(RwLock::new(DataConnectors { kv: KV::new() }));
+    let assets = StaticAssets::new(&args.path).map(Data::new);
+    // Handlers only receive the details of the proxy headers behind a trusted proxy
+    let trusted_proxy =
+        (project.server.trusted_proxy == Some(true)).then(|| Data::new(TrustedProxy));
+
+    create_kv_stores(&data, &project, &routes.table());
+    print_routes(&routes.table(), &hostname, port);
+
+    if assets.is_some() {
+        println!(
+            "📁 Serving static assets from: {}",
+            args.path.join(assets::PUBLIC_FOLDER).display()
+        );
+    }
+
+    if args.watch || args.dev() {
+        println!("👀 Watching the project for changes");
+        watch_routes(
+            args.clone(),
+            Data::clone(&routes),
+            Data::clone(&data),
+            dev.clone(),
+        );
+    }
 
     let server = HttpServer::new(move || {
+        let mut headers = middleware::DefaultHeaders::new();
+        // Headers are validated when loading the project configuration
+        for (key, value) in project.headers.iter() {
+            headers = headers.add((key.as_str(), value.as_str()));
+        }
+
         let mut app = App::new()
+            // Global headers. Handlers can override them
+            .wrap(headers)
             // enable logger
             .wrap(middleware::Logger::default())
             // Clean path before sending it to the service
             .wrap(middleware::NormalizePath::trim())
             .app_data(Data::clone(&routes))
             .app_data(Data::clone(&data))
-            .service(web::resource("/_debug").to(debug));
+            .app_data(Data::clone(&concurrency_limit))
+            .service(web::resource("/_debug").to(debug))
+            .service(web::resource(METRICS_PATH).to(metrics))
+            // Routes may contain dynamic segments, so the wasm_handler
+            // is in charge of finding the right one
+            .default_service(web::to(wasm_handler));
 
-        // Append routes to the current service
-        for route in routes.routes.iter() {
-            app = app.service(web::resource(&route.path).to(wasm_handler));
+        if let Some(assets) = &assets {
+            app = app.app_data(Data::clone(assets));
+        }
 
-            // Configure KV
-            if let Some(namespace) = route.config.as_ref().and_then(|c| c.data_kv_namespace()) {
-                data.write().unwrap().kv.create_store(&namespace);
-            }
+        if let Some(trusted_proxy) = &trusted_proxy {
+            app = app.app_data(Data::clone(trusted_proxy));
+        }
+
+        if let Some(dev) = &dev {
+            app = app
+                .app_data(Data::clone(dev))
+                .service(web::resource(dev::LIVE_RELOAD_PATH).to(live_reload));
         }
 
         app
     })
-    .bind((args.hostname.as_str(), args.port))?;
+    .bind((hostname.as_str(), port))?;
 
     println!(
         "🚀 Start serving requests at http://{}:{}\n",
-        &args.hostname, args.port
+        &hostname, port
     );
 
     server.run().await
@@ -0,0 +1,249 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::path::{Component, Path};
+
+/// Name of the file that lists the files to ignore. It's loaded from the root folder
+pub const IGNORE_FILE: &str = ".wwsignore";
+
+/// Folders that never contain routes. The project may include them again
+/// with a negated pattern like `!target/`
+const DEFAULT_RULES: [&str; 2] = ["node_modules/", "target/"];
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A single gitignore-style pattern
+#[derive(Debug)]
+struct Rule {
+    /// The glob to match
+    pattern: Pattern,
+    /// The pattern starts with `!`, so it includes the file again
+    negated: bool,
+    /// The pattern ends with `/`, so it only matches folders
+    dir_only: bool,
+    /// The pattern contains a `/`, so it matches the path from the root folder.
+    /// Otherwise, it matches the name at any level
+    anchored: bool,
+}
+
+impl Rule {
+    // Parse a line using the gitignore format. Empty lines and comments return None
+    fn parse(line: &str) -> Result<Option<Self>, String> {
+        let mut line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let negated = match line.strip_prefix('!') {
+            Some(rest) => {
+                line = rest;
+                true
+            }
+            None => false,
+        };
+        // Escaped characters at the beginning
+        if line.starts_with("\\#") || line.starts_with("\\!") {
+            line = &line[1..];
+        }
+
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        let pattern =
+            Pattern::new(line).map_err(|err| format!("invalid pattern \"{}\": {}", line, err))?;
+
+        Ok(Some(Self {
+            pattern,
+            negated,
+            dir_only,
+            anchored,
+        }))
+    }
+
+    // Check if the rule matches the given relative path
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            self.pattern.matches_with(path, MATCH_OPTIONS)
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            self.pattern.matches_with(name, MATCH_OPTIONS)
+        }
+    }
+}
+
+/// A list of gitignore-style patterns to skip files during the route discovery.
+/// The rules follow the same format as `.gitignore` files:
+///
+/// ```text
+/// # Comments and empty lines are ignored
+/// helpers/          =>  Any folder called helpers
+/// /scripts          =>  The scripts file or folder in the root
+/// *.test.js         =>  Files ending in .test.js at any level
+/// api/**/_*.js      =>  Files starting with _ in any subfolder of api
+/// !api/_main.js     =>  Include a file that a previous pattern ignored
+/// ```
+///
+/// The last matching rule wins. Files inside an ignored folder are always ignored.
+#[derive(Debug)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl Default for IgnoreRules {
+    fn default() -> Self {
+        let mut rules = Self { rules: Vec::new() };
+
+        for line in DEFAULT_RULES {
+            // Default rules are valid
+            rules.add(line).unwrap();
+        }
+
+        rules
+    }
+}
+
+impl IgnoreRules {
+    /// Build the rules for a project. It includes the default rules, the given
+    /// patterns from the project configuration and the ones in the `.wwsignore`
+    /// file if available
+    pub fn load(base_path: &Path, patterns: &[String]) -> Result<Self, String> {
+        let mut rules = Self::default();
+
+        for pattern in patterns.iter() {
+            rules.add(pattern)?;
+        }
+
+        let path = base_path.join(IGNORE_FILE);
+
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for (number, line) in contents.lines().enumerate() {
+                rules
+                    .add(line)
+                    .map_err(|err| format!("{}:{}: {}", path.display(), number + 1, err))?;
+            }
+        }
+
+        Ok(rules)
+    }
+
+    /// Add a new rule using the gitignore format
+    pub fn add(&mut self, line: &str) -> Result<(), String> {
+        if let Some(rule) = Rule::parse(line)? {
+            self.rules.push(rule);
+        }
+
+        Ok(())
+    }
+
+    /// Check if the given file must be ignored. The path is relative to the root folder
+    pub fn is_ignored(&self, relative_path: &Path) -> bool {
+        let components: Vec<&str> = relative_path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        // A file in an ignored folder can't be included again
+        for depth in 1..components.len() {
+            if self.matches(&components[..depth].join("/"), true) {
+                return true;
+            }
+        }
+
+        self.matches(&components.join("/"), false)
+    }
+
+    // Find the last rule that matches the path
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .map(|rule| !rule.negated)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_rules(lines: &[&str]) -> IgnoreRules {
+        let mut rules = IgnoreRules::default();
+
+        for line in lines {
+            rules.add(line).unwrap();
+        }
+
+        rules
+    }
+
+    #[test]
+    fn ignore_default_folders() {
+        let rules = IgnoreRules::default();
+
+        assert!(rules.is_ignored(Path::new("node_modules/lib/index.js")));
+        assert!(rules.is_ignored(Path::new("api/node_modules/lib/index.js")));
+        assert!(rules.is_ignored(Path::new("target/wasm32-wasi/release/api.wasm")));
+        assert!(!rules.is_ignored(Path::new("api/target.js")));
+        assert!(!rules.is_ignored(Path::new("index.js")));
+    }
+
+    #[test]
+    fn ignore_gitignore_patterns() {
+        let rules = build_rules(&[
+            "# Comment",
+            "",
+            "helpers/",
+            "/scripts",
+            "*.test.js",
+            "api/**/_*.js",
+            "!api/v1/_main.js",
+        ]);
+
+        assert!(rules.is_ignored(Path::new("helpers/db.js")));
+        assert!(rules.is_ignored(Path::new("api/helpers/db.js")));
+        assert!(!rules.is_ignored(Path::new("api/helpers.js")));
+        assert!(rules.is_ignored(Path::new("scripts/build.js")));
+        assert!(rules.is_ignored(Path::new("scripts")));
+        assert!(!rules.is_ignored(Path::new("api/scripts/build.js")));
+        assert!(rules.is_ignored(Path::new("users.test.js")));
+        assert!(rules.is_ignored(Path::new("api/users.test.js")));
+        assert!(rules.is_ignored(Path::new("api/v1/_internal.js")));
+        assert!(!rules.is_ignored(Path::new("api/v1/_main.js")));
+        assert!(!rules.is_ignored(Path::new("api/users.js")));
+    }
+
+    #[test]
+    fn ignore_negated_folders() {
+        let rules = build_rules(&["!target/", "build/", "!build/api.js"]);
+
+        assert!(!rules.is_ignored(Path::new("target/api.wasm")));
+        // Files in ignored folders can't be included again
+        assert!(rules.is_ignored(Path::new("build/api.js")));
+    }
+
+    #[test]
+    fn ignore_invalid_patterns() {
+        assert!(IgnoreRules::default().add("api/[").is_err());
+    }
+}
@@ -0,0 +1,1112 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//
+// Declare the different routes for the project
+// based on the files in the given folder
+//
+mod ignore;
+mod table;
+
+use crate::assets::PUBLIC_FOLDER;
+use crate::config::{Config, LimitsConfig, ProjectConfig};
+use crate::runner::{ConcurrencyLimit, Limits, Runner, Runtime};
+use actix_web::http::Method;
+use clap::ValueEnum;
+use glob::glob;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+pub use ignore::IgnoreRules;
+pub use table::{RouteMatch, RouteTable, RouteTree};
+
+/// A piece of a route path. Folders and files wrapped in brackets (`[id]`) are
+/// considered dynamic and match any value in that position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RouteSegment {
+    /// A fixed segment that must match the request path exactly
+    Static(String),
+    /// A dynamic segment (`[id]`). It matches any single path segment and stores it
+    /// using the given name
+    Dynamic(String),
+    /// A catch-all segment (`[...slug]`). It matches one or more path segments
+    CatchAll(String),
+    /// An optional catch-all segment (`[[...slug]]`). It matches zero or more
+    /// path segments
+    OptionalCatchAll(String),
+}
+
+impl RouteSegment {
+    /// Parse a single segment from a route path
+    fn parse(segment: &str) -> Self {
+        let optional_rest = segment
+            .strip_prefix("[[...")
+            .and_then(|s| s.strip_suffix("]]"));
+
+        if let Some(name) = optional_rest.filter(|n| !n.is_empty()) {
+            return Self::OptionalCatchAll(name.to_string());
+        }
+
+        match segment.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            Some(name) => match name.strip_prefix("...") {
+                Some(rest) if !rest.is_empty() => Self::CatchAll(rest.to_string()),
+                _ if !name.is_empty() => Self::Dynamic(name.to_string()),
+                _ => Self::Static(segment.to_string()),
+            },
+            None => Self::Static(segment.to_string()),
+        }
+    }
+
+    /// Check if the segment captures the rest of the path
+    fn is_catch_all(&self) -> bool {
+        matches!(self, Self::CatchAll(_) | Self::OptionalCatchAll(_))
+    }
+
+    /// The priority of the segment when multiple routes match the same path.
+    /// Lower values are more specific and take precedence.
+    fn priority(&self) -> u8 {
+        match self {
+            Self::Static(_) => 0,
+            Self::Dynamic(_) => 1,
+            Self::CatchAll(_) => 2,
+            Self::OptionalCatchAll(_) => 3,
+        }
+    }
+}
+
+/// The value captured by a dynamic or catch-all segment
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RouteParam {
+    /// A single path segment, captured by dynamic segments
+    Segment(String),
+    /// The rest of the path, captured by catch-all segments
+    Rest(Vec<String>),
+}
+
+/// The params captured when a route matches a given URL path
+pub type RouteParams = HashMap<String, RouteParam>;
+
+/// The role of a route in the project. Files with a reserved name (like `_404.wasm`)
+/// don't reply to their own URL path. Instead, they apply to all the routes in the
+/// same folder and below.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteKind {
+    /// A regular handler that replies to its URL path
+    Handler,
+    /// Replies to the paths that don't match any route (`_404`)
+    NotFound,
+    /// Replies when a handler fails (`_error`)
+    Error,
+    /// Runs before the handlers and may change the request or reply to it (`_middleware`)
+    Middleware,
+}
+
+impl RouteKind {
+    /// Retrieve the kind from the name of the file, without the extension
+    fn from_name(name: &str) -> Self {
+        match name {
+            "_404" => Self::NotFound,
+            "_error" => Self::Error,
+            "_middleware" => Self::Middleware,
+            _ => Self::Handler,
+        }
+    }
+}
+
+/// An existing route in the project. It contains a reference to the handler, the URL path,
+/// the runner and configuration. Note that URL paths are calculated based on the file path.
+///
+/// # Examples
+///
+/// ```text
+/// index.wasm          =>  /
+/// api/index.wasm      =>  /api
+/// api/v2/ping.wasm    =>  /api/v2/ping
+/// users/[id].wasm     =>  /users/[id]
+/// [id]/index.js       =>  /[id]
+/// docs/[...slug].js   =>  /docs/[...slug]
+/// ```
+///
+/// Handlers may reply only to a specific HTTP method by adding it before the extension:
+///
+/// ```text
+/// users/index.get.wasm  =>  GET /users
+/// users/index.post.js   =>  POST /users
+/// ```
+///
+/// Special handlers apply to the folder they are in:
+///
+/// ```text
+/// _404.wasm           =>  Paths that don't match any route
+/// api/_error.js       =>  Handler failures in /api and below
+/// api/_middleware.js  =>  Requests to /api and below
+/// ```
+#[derive(Clone)]
+pub struct Route {
+    /// The wasm module that will manage the route
+    pub handler: PathBuf,
+    /// The role of the route
+    pub kind: RouteKind,
+    /// The URL path. For special handlers, it's the path of the folder they apply to
+    pub path: String,
+    /// The URL path split in segments. It's used to match dynamic routes
+    pub segments: Vec<RouteSegment>,
+    /// The HTTP methods this route replies to. None means all methods
+    pub methods: Option<Vec<Method>>,
+    /// The preconfigured runner
+    pub runner: Runner,
+    /// The associated configuration if available
+    pub config: Option<Config>,
+    /// The Key/Value namespace the route accesses if available
+    pub kv_namespace: Option<String>,
+    /// Extra headers for the route responses. They come from the project configuration
+    pub headers: HashMap<String, String>,
+    /// Execution limits for the handler
+    pub limits: Limits,
+    /// Limits the requests the handler runs at the same time if configured
+    pub concurrency: Option<Arc<ConcurrencyLimit>>,
+}
+
+impl Route {
+    /// Initialize a new route from the given folder and filepath. It will calculate the
+    /// proper URL path based on the filename.
+    ///
+    /// This method also initializes the Runner and loads the Config if available.
+    /// When the previous route table has an up to date runner for the same file,
+    /// it reuses it instead of compiling the module again.
+    fn new(
+        runtime: &Runtime,
+        base_path: &Path,
+        filepath: PathBuf,
+        previous: Option<&RouteTable>,
+    ) -> Result<Self, String> {
+        let mut runner = match previous.and_then(|table| table.find_handler(&filepath)) {
+            Some(route) if !route.runner.is_outdated(&filepath) => route.runner.clone(),
+            _ => Runner::new(runtime, &filepath)
+                .map_err(|err| format!("Error loading {}: {}", filepath.display(), err))?,
+        };
+        // Load configuration. An invalid file fails instead of dropping the
+        // whole configuration, like the limits of the handler
+        let mut config_path = filepath.clone();
+        config_path.set_extension("toml");
+        let mut config = None::<Config>;
+
+        if fs::metadata(&config_path).is_ok() {
+            config = Some(Config::try_from_file(config_path)?);
+        }
+
+        // The configuration version applies to the modules that don't declare
+        // their protocol version
+        runner.set_fallback_protocol(config.as_ref().and_then(|c| c.protocol()));
+
+        let path = Self::retrieve_route(base_path, &filepath);
+        let segments = Self::retrieve_segments(&path);
+
+        if let Some(pos) = segments.iter().position(|s| s.is_catch_all()) {
+            if pos != segments.len() - 1 {
+                eprintln!(
+                    "Catch-all segments must be the last part of the route. {} won't match any path",
+                    &path
+                );
+            }
+        }
+
+        // The method in the filename takes precedence over the configuration
+        let methods = match Self::retrieve_method(&filepath) {
+            Some(method) => Some(vec![method]),
+            None => config.as_ref().and_then(|c| c.methods()),
+        };
+
+        let kind = Self::strip_method(&filepath)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(RouteKind::from_name)
+            .unwrap_or(RouteKind::Handler);
+
+        let kv_namespace = config.as_ref().and_then(|c| c.data_kv_namespace());
+
+        Ok(Self {
+            segments,
+            path,
+            methods,
+            kind,
+            handler: filepath,
+            runner,
+            config,
+            kv_namespace,
+            headers: HashMap::new(),
+            limits: Limits::default(),
+            concurrency: None,
+        })
+    }
+
+    // Apply the project configuration. Route overrides take precedence over the
+    // handler configuration, but the method in the filename still wins as it's
+    // part of the route definition.
+    fn apply_project_config(&mut self, project: &ProjectConfig) {
+        self.limits = Limits::new(
+            self.config.as_ref().and_then(|c| c.limits.as_ref()),
+            &project.limits,
+        );
+        self.concurrency = self
+            .limits
+            .concurrency
+            .map(|concurrency| Arc::new(ConcurrencyLimit::new(concurrency, self.limits.queue)));
+
+        // The engine only counts the fuel when a handler had a limit at startup
+        if self.limits.fuel.is_some() && !self.runner.supports_fuel() {
+            eprintln!(
+                "⚠️  The fuel limit of {} requires to restart the server",
+                self.handler.display()
+            );
+        }
+
+        if self.kv_namespace.is_none() {
+            self.kv_namespace = project.data.kv.default.clone();
+        }
+
+        if self.kind != RouteKind::Handler {
+            return;
+        }
+
+        if let Some(route) = project.route(&self.path) {
+            if let Some(methods) = route.methods() {
+                if Self::retrieve_method(&self.handler).is_none() {
+                    self.methods = Some(methods);
+                }
+            }
+
+            if route.kv.is_some() {
+                self.kv_namespace = route.kv.clone();
+            }
+
+            self.headers = route.headers.clone();
+        }
+    }
+
+    // Process the given path to return the proper route for the API.
+    // It will transform paths like test/index.wasm into /test.
+    fn retrieve_route(base_path: &Path, path: &Path) -> String {
+        let relative = path.strip_prefix(base_path).unwrap_or(path);
+        let mut segments: Vec<String> = Self::strip_method(relative)
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(segment) => Some(segment.to_string_lossy().to_string()),
+                _ => None,
+            })
+            .collect();
+
+        // Index files and special handlers reply to the folder path
+        let is_folder =
+            |name: &String| name == "index" || RouteKind::from_name(name) != RouteKind::Handler;
+
+        if segments.last().map(is_folder).unwrap_or(false) {
+            segments.pop();
+        }
+
+        format!("/{}", segments.join("/"))
+    }
+
+    // Retrieve the HTTP method from filenames like index.get.wasm
+    fn retrieve_method(path: &Path) -> Option<Method> {
+        let method = path.with_extension("");
+        let method = method.extension()?.to_str()?;
+
+        HTTP_METHODS
+            .iter()
+            .find(|m| m.as_str().eq_ignore_ascii_case(method))
+            .cloned()
+    }
+
+    // Remove the extension and the HTTP method (if available) from the given path.
+    // It will transform paths like test/index.get.wasm into test/index.
+    fn strip_method(path: &Path) -> PathBuf {
+        let stripped = path.with_extension("");
+
+        if Self::retrieve_method(path).is_some() {
+            stripped.with_extension("")
+        } else {
+            stripped
+        }
+    }
+
+    /// Split the given route path into segments. Empty segments are ignored,
+    /// so the root path doesn't contain any segment.
+    pub fn retrieve_segments(route_path: &str) -> Vec<RouteSegment> {
+        route_path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(RouteSegment::parse)
+            .collect()
+    }
+
+    /// Check if the given URL path matches this route. It returns the values of
+    /// the dynamic and catch-all segments when it matches or None otherwise.
+    pub fn match_path(&self, url_path: &str) -> Option<RouteParams> {
+        match_segments(&self.segments, url_path)
+    }
+
+    /// Check if this route replies to the given HTTP method
+    pub fn allows(&self, method: &Method) -> bool {
+        match &self.methods {
+            Some(methods) => methods.contains(method),
+            None => true,
+        }
+    }
+}
+
+/// HTTP methods that can be used in handler filenames
+const HTTP_METHODS: [Method; 7] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+    Method::HEAD,
+    Method::OPTIONS,
+];
+
+/// Compare the route segments with the given URL path. It captures the values
+/// of the dynamic and catch-all segments in the process. The captured values
+/// are percent-decoded, so paths with invalid UTF-8 values don't match.
+pub fn match_segments(segments: &[RouteSegment], url_path: &str) -> Option<RouteParams> {
+    let url_segments: Vec<&str> = url_path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut params = HashMap::new();
+
+    for (index, segment) in segments.iter().enumerate() {
+        match segment {
+            RouteSegment::Static(name) => {
+                if url_segments.get(index) != Some(&name.as_str()) {
+                    return None;
+                }
+            }
+            RouteSegment::Dynamic(name) => {
+                let value = decode_segment(url_segments.get(index)?)?;
+                params.insert(name.clone(), RouteParam::Segment(value));
+            }
+            RouteSegment::CatchAll(name) | RouteSegment::OptionalCatchAll(name) => {
+                // Catch-all segments are only valid at the end of the route
+                if index != segments.len() - 1 {
+                    return None;
+                }
+
+                let rest: Vec<String> = url_segments
+                    .iter()
+                    .skip(index)
+                    .map(|s| decode_segment(s))
+                    .collect::<Option<Vec<String>>>()?;
+
+                if rest.is_empty() && matches!(segment, RouteSegment::CatchAll(_)) {
+                    return None;
+                }
+
+                params.insert(name.clone(), RouteParam::Rest(rest));
+
+                return Some(params);
+            }
+        }
+    }
+
+    if url_segments.len() != segments.len() {
+        return None;
+    }
+
+    Some(params)
+}
+
+// Decode the percent-encoded characters of a URL path segment. It fails when
+// the result is not valid UTF-8
+fn decode_segment(segment: &str) -> Option<String> {
+    percent_decode_str(segment)
+        .decode_utf8()
+        .ok()
+        .map(|decoded| decoded.into_owned())
+}
+
+// Check if the URL path is inside the folder represented by the given segments.
+// It returns the values of the dynamic segments of the folder.
+fn match_prefix(segments: &[RouteSegment], url_path: &str) -> Option<RouteParams> {
+    let mut folder = segments.to_vec();
+    folder.push(RouteSegment::OptionalCatchAll(String::new()));
+
+    let mut params = match_segments(&folder, url_path)?;
+    params.remove("");
+
+    Some(params)
+}
+
+// Sort routes segments by how specific they are. Static segments win over
+// dynamic ones, and dynamic segments win over catch-all ones. Segments are
+// compared from left to right, so `/users/[id]` takes precedence over `/[user]/posts`.
+fn compare_segments(a: &[RouteSegment], b: &[RouteSegment]) -> Ordering {
+    let priorities =
+        |segments: &[RouteSegment]| -> Vec<u8> { segments.iter().map(|s| s.priority()).collect() };
+
+    priorities(a).cmp(&priorities(b))
+}
+
+/// Defines how to proceed when multiple handlers reply to the same route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    /// Refuse to start the server
+    Fail,
+    /// Keep a single handler for every route. Named files win over index files
+    /// (`api.js` over `api/index.js`), Wasm modules win over JavaScript files
+    /// (`api.wasm` over `api.js`) and, finally, the first handler path in
+    /// alphabetical order wins
+    Precedence,
+}
+
+// Calculates an identifier for the set of paths a route matches. Routes
+// sharing the same key are ambiguous, as `/users/[id]` and `/users/[name]`.
+fn route_key(segments: &[RouteSegment]) -> String {
+    let parts: Vec<&str> = segments
+        .iter()
+        .map(|segment| match segment {
+            RouteSegment::Static(name) => name.as_str(),
+            RouteSegment::Dynamic(_) => "[]",
+            RouteSegment::CatchAll(_) => "[...]",
+            RouteSegment::OptionalCatchAll(_) => "[[...]]",
+        })
+        .collect();
+
+    format!("/{}", parts.join("/"))
+}
+
+// Calculates the identifiers of the requests a route replies to. Routes for
+// all methods don't conflict with method specific ones, as the latter take
+// precedence.
+fn conflict_keys(
+    kind: RouteKind,
+    segments: &[RouteSegment],
+    methods: &Option<Vec<Method>>,
+) -> Vec<(RouteKind, Option<Method>, String)> {
+    let key = route_key(segments);
+
+    match methods {
+        Some(methods) if kind == RouteKind::Handler => methods
+            .iter()
+            .map(|method| (kind, Some(method.clone()), key.clone()))
+            .collect(),
+        _ => vec![(kind, None, key)],
+    }
+}
+
+// Compare two handlers that reply to the same route. The one that comes
+// first takes precedence. Check ConflictStrategy::Precedence for the rules.
+fn compare_handlers(a: &Path, b: &Path) -> Ordering {
+    let is_index = |path: &Path| {
+        Route::strip_method(path)
+            .file_name()
+            .map(|s| s == "index")
+            .unwrap_or(false)
+    };
+    let is_js = |path: &Path| Runner::is_js_file(path);
+
+    is_index(a)
+        .cmp(&is_index(b))
+        .then_with(|| is_js(a).cmp(&is_js(b)))
+        .then_with(|| a.cmp(b))
+}
+
+// Look for routes that reply to the same requests. It returns the conflicting
+// routes grouped by HTTP method and route key. Every group is sorted by precedence.
+fn find_conflicts(routes: &[Route]) -> Vec<(Option<Method>, Vec<&Route>)> {
+    let mut groups: HashMap<(RouteKind, Option<Method>, String), Vec<&Route>> = HashMap::new();
+
+    for route in routes.iter() {
+        for key in conflict_keys(route.kind, &route.segments, &route.methods) {
+            groups.entry(key).or_default().push(route);
+        }
+    }
+
+    let mut conflicts: Vec<(Option<Method>, Vec<&Route>)> = groups
+        .into_iter()
+        .filter(|(_, group)| group.len() > 1)
+        .map(|((_, method, _), mut group)| {
+            group.sort_by(|a, b| compare_handlers(&a.handler, &b.handler));
+            (method, group)
+        })
+        .collect();
+
+    conflicts.sort_by(|a, b| a.1[0].path.cmp(&b.1[0].path));
+
+    conflicts
+}
+
+/// Format a list of HTTP methods like GET, POST
+pub fn join_methods(methods: &[Method]) -> String {
+    methods
+        .iter()
+        .map(|m| m.as_str())
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+/// Find the handler files in the given folder. It skips the static assets and
+/// the files that match the ignore rules. In verbose mode, it logs every skipped file.
+pub fn find_handlers(
+    base_path: &Path,
+    project: &ProjectConfig,
+    verbose: bool,
+) -> Result<Vec<PathBuf>, String> {
+    let mut handlers = Vec::new();
+    let path = Path::new(&base_path);
+    let ignore = IgnoreRules::load(base_path, &project.ignore)?;
+
+    // Items to iterate over
+    let glob_items = glob(path.join("**/*.wasm").as_os_str().to_str().unwrap())
+        .expect("Failed to read the current directory")
+        .chain(
+            glob(path.join("**/*.js").as_os_str().to_str().unwrap())
+                .expect("Failed to read the current directory"),
+        );
+
+    for entry in glob_items {
+        match entry {
+            Ok(filepath) => {
+                let relative = filepath.strip_prefix(base_path).unwrap_or(&filepath);
+
+                // Files in the public folder are static assets
+                if relative.starts_with(PUBLIC_FOLDER) {
+                    continue;
+                }
+
+                if ignore.is_ignored(relative) {
+                    if verbose {
+                        println!("⏭  Skipping ignored file: {}", filepath.display());
+                    }
+                    continue;
+                }
+
+                handlers.push(filepath);
+            }
+            Err(e) => println!("Could not read the file {:?}", e),
+        }
+    }
+
+    Ok(handlers)
+}
+
+/// Read the limits of the given handlers from their configuration files.
+/// Handlers without limits or with an invalid file are skipped. Loading the
+/// routes reports the invalid files
+pub fn handler_limits(handlers: &[PathBuf]) -> Vec<LimitsConfig> {
+    handlers
+        .iter()
+        .filter_map(|handler| {
+            let config_path = handler.with_extension("toml");
+
+            if fs::metadata(&config_path).is_err() {
+                return None;
+            }
+
+            Config::try_from_file(config_path).ok()?.limits
+        })
+        .collect()
+}
+
+/// Initialize the list of routes from the given folder. This method will look for
+/// all `**/*.wasm` files and will create the associated routes. This routing approach
+/// is pretty popular in web development and static sites.
+///
+/// Multiple handlers may reply to the same route, like `api.js` and `api/index.wasm`.
+/// Depending on the given strategy, this method will return an error describing
+/// the conflicts or will keep the handler that takes precedence.
+///
+/// Files that match the ignore rules don't become routes. The rules come from
+/// the project configuration and the `.wwsignore` file, and they always skip the
+/// `node_modules` and `target` folders. In verbose mode, it logs every skipped file.
+/// The project configuration may also change the settings of specific routes.
+///
+/// All the runners share the given runtime. When reloading the project, the previous
+/// table provides the runners for the handlers that didn't change, so only the new
+/// and updated modules are compiled.
+///
+/// The routes are sorted by precedence: static routes come first, then dynamic ones
+/// and finally catch-all routes. The returned table finds the route that replies to
+/// every path.
+pub fn initialize_routes(
+    runtime: &Runtime,
+    base_path: &Path,
+    project: &ProjectConfig,
+    strategy: ConflictStrategy,
+    verbose: bool,
+    previous: Option<&RouteTable>,
+) -> Result<RouteTable, String> {
+    let mut routes = Vec::new();
+
+    for filepath in find_handlers(base_path, project, verbose)? {
+        let mut route = Route::new(runtime, base_path, filepath, previous)?;
+        route.apply_project_config(project);
+        routes.push(route);
+    }
+
+    let conflicts = find_conflicts(&routes);
+
+    if !conflicts.is_empty() {
+        let mut ignored = HashSet::new();
+        let mut report = String::new();
+
+        for (method, group) in conflicts.iter() {
+            let method = match method {
+                Some(method) => format!("{} ", method),
+                None => String::new(),
+            };
+
+            let description = match group[0].kind {
+                RouteKind::Handler => "Route",
+                RouteKind::NotFound => "Not found handler for",
+                RouteKind::Error => "Error handler for",
+                RouteKind::Middleware => "Middleware for",
+            };
+
+            report.push_str(&format!(
+                "\n    - {} {}{} is defined by multiple handlers:",
+                description, method, group[0].path
+            ));
+
+            for route in group.iter() {
+                report.push_str(&format!("\n      => {}", route.handler.display()));
+            }
+
+            ignored.extend(group.iter().skip(1).map(|route| route.handler.clone()));
+        }
+
+        match strategy {
+            ConflictStrategy::Fail => {
+                return Err(format!(
+                    "Found conflicting routes:{}\n\nRemove the duplicated handlers or start the server with `--route-conflicts precedence`",
+                    report
+                ));
+            }
+            ConflictStrategy::Precedence => {
+                println!(
+                    "⚠️  Found conflicting routes. The first handler takes precedence:{}",
+                    report
+                );
+            }
+        }
+
+        routes.retain(|route| !ignored.contains(&route.handler));
+    }
+
+    for path in project.routes.keys() {
+        if !routes
+            .iter()
+            .any(|route| route.kind == RouteKind::Handler && &route.path == path)
+        {
+            println!(
+                "⚠️  The project configuration changes the {} route, but there's no handler for it",
+                path
+            );
+        }
+    }
+
+    routes.sort_by(|a, b| compare_segments(&a.segments, &b.segments));
+
+    Ok(RouteTable::new(routes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+
+    #[test]
+    fn invalid_handler_config() {
+        let folder =
+            std::env::temp_dir().join(format!("wws-invalid-config-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(
+            folder.join("index.wasm"),
+            r#"(module (func (export "_start")))"#,
+        )
+        .unwrap();
+        fs::write(folder.join("index.toml"), "[limits]\ntimeot = 500\n").unwrap();
+
+        let config = EngineConfig {
+            cache: Some(false),
+            ..EngineConfig::default()
+        };
+        let runtime = Runtime::new(&config, &folder, None, false).unwrap();
+        let result = initialize_routes(
+            &runtime,
+            &folder,
+            &ProjectConfig::default(),
+            ConflictStrategy::Fail,
+            false,
+            None,
+        );
+        fs::remove_dir_all(&folder).unwrap();
+
+        // A typo in the limits fails instead of dropping the handler configuration
+        let err = result.err().unwrap();
+        assert!(err.contains("index.toml"), "{}", err);
+        assert!(err.contains("unknown field `timeot`"), "{}", err);
+    }
+
+    #[test]
+    fn unix_route_index_path_retrieval() {
+        let check_route = |path: &str, expected_route: &str| {
+            assert_eq!(
+                Route::retrieve_route(Path::new("."), &PathBuf::from(path)),
+                String::from(expected_route),
+            )
+        };
+
+        // In a subfolder
+        check_route("examples/index.js", "/examples");
+        check_route("examples/index.wasm", "/examples");
+
+        // Multiple levels
+        check_route("examples/api/index.js", "/examples/api");
+        check_route("examples/api/index.wasm", "/examples/api");
+
+        // Root
+        check_route("index.js", "/");
+        check_route("index.wasm", "/");
+    }
+
+    #[test]
+    fn unix_route_path_retrieval() {
+        let check_route = |path: &str, expected_route: &str| {
+            assert_eq!(
+                Route::retrieve_route(Path::new("."), &PathBuf::from(path)),
+                String::from(expected_route),
+            )
+        };
+
+        // In a subfolder
+        check_route("examples/handler.js", "/examples/handler");
+        check_route("examples/handler.wasm", "/examples/handler");
+
+        // Multiple levels
+        check_route("examples/api/handler.js", "/examples/api/handler");
+        check_route("examples/api/handler.wasm", "/examples/api/handler");
+
+        // Root
+        check_route("handler.js", "/handler");
+        check_route("handler.wasm", "/handler");
+    }
+
+    #[test]
+    fn unix_route_dynamic_path_retrieval() {
+        let check_route = |path: &str, expected_route: &str| {
+            assert_eq!(
+                Route::retrieve_route(Path::new("."), &PathBuf::from(path)),
+                String::from(expected_route),
+            )
+        };
+
+        check_route("users/[id].wasm", "/users/[id]");
+        check_route("users/[id]/index.js", "/users/[id]");
+        check_route("[id]/index.js", "/[id]");
+        check_route("users/[id]/posts/[post].js", "/users/[id]/posts/[post]");
+    }
+
+    #[test]
+    fn unix_route_method_path_retrieval() {
+        let check_route = |path: &str, expected_route: &str, expected_method: Option<Method>| {
+            assert_eq!(
+                Route::retrieve_route(Path::new("."), &PathBuf::from(path)),
+                String::from(expected_route),
+            );
+            assert_eq!(
+                Route::retrieve_method(&PathBuf::from(path)),
+                expected_method
+            );
+        };
+
+        check_route("users/index.get.wasm", "/users", Some(Method::GET));
+        check_route("users/index.post.js", "/users", Some(Method::POST));
+        check_route("users/[id].DELETE.js", "/users/[id]", Some(Method::DELETE));
+        check_route("users.put.wasm", "/users", Some(Method::PUT));
+        check_route("index.get.js", "/", Some(Method::GET));
+        // Unknown methods are part of the route
+        check_route("users/index.list.js", "/users/index.list", None);
+        check_route("api/v1.2/ping.js", "/api/v1.2/ping", None);
+    }
+
+    #[test]
+    fn unix_route_special_path_retrieval() {
+        let check_route = |path: &str, expected_route: &str| {
+            assert_eq!(
+                Route::retrieve_route(Path::new("."), &PathBuf::from(path)),
+                String::from(expected_route),
+            )
+        };
+
+        check_route("_404.wasm", "/");
+        check_route("_error.js", "/");
+        check_route("api/_404.js", "/api");
+        check_route("api/[version]/_error.wasm", "/api/[version]");
+        check_route("_middleware.wasm", "/");
+        check_route("api/_middleware.js", "/api");
+        // Other files starting with _ are regular handlers
+        check_route("api/_internal.js", "/api/_internal");
+    }
+
+    #[test]
+    fn route_prefix_matching() {
+        let check_match = |folder: &str, path: &str, expected: bool| {
+            assert_eq!(
+                match_prefix(&Route::retrieve_segments(folder), path).is_some(),
+                expected
+            )
+        };
+
+        check_match("/", "/", true);
+        check_match("/", "/api/users", true);
+        check_match("/api", "/api", true);
+        check_match("/api", "/api/users/1", true);
+        check_match("/api", "/apis", false);
+        check_match("/api", "/", false);
+        check_match("/[user]/posts", "/angel/posts/1", true);
+
+        assert_eq!(
+            match_prefix(&Route::retrieve_segments("/[user]"), "/angel/posts"),
+            Some(HashMap::from([(
+                String::from("user"),
+                RouteParam::Segment(String::from("angel"))
+            )]))
+        );
+    }
+
+    #[test]
+    fn unix_route_catch_all_path_retrieval() {
+        let check_route = |path: &str, expected_route: &str| {
+            assert_eq!(
+                Route::retrieve_route(Path::new("."), &PathBuf::from(path)),
+                String::from(expected_route),
+            )
+        };
+
+        check_route("docs/[...slug].wasm", "/docs/[...slug]");
+        check_route("docs/[...slug]/index.js", "/docs/[...slug]");
+        check_route("[[...slug]].js", "/[[...slug]]");
+    }
+
+    #[test]
+    fn route_segments_parsing() {
+        assert_eq!(Route::retrieve_segments("/"), Vec::<RouteSegment>::new());
+        assert_eq!(
+            Route::retrieve_segments("/users/[id]"),
+            vec![
+                RouteSegment::Static(String::from("users")),
+                RouteSegment::Dynamic(String::from("id"))
+            ]
+        );
+        assert_eq!(
+            Route::retrieve_segments("/docs/[...slug]"),
+            vec![
+                RouteSegment::Static(String::from("docs")),
+                RouteSegment::CatchAll(String::from("slug"))
+            ]
+        );
+        assert_eq!(
+            Route::retrieve_segments("/docs/[[...slug]]"),
+            vec![
+                RouteSegment::Static(String::from("docs")),
+                RouteSegment::OptionalCatchAll(String::from("slug"))
+            ]
+        );
+        // Empty brackets are not a valid dynamic segment
+        assert_eq!(
+            Route::retrieve_segments("/[]"),
+            vec![RouteSegment::Static(String::from("[]"))]
+        );
+        assert_eq!(
+            Route::retrieve_segments("/[...]"),
+            vec![RouteSegment::Dynamic(String::from("..."))]
+        );
+    }
+
+    #[test]
+    fn route_dynamic_path_matching() {
+        let check_match = |route: &str, path: &str, expected: Option<Vec<(&str, &str)>>| {
+            assert_eq!(
+                match_segments(&Route::retrieve_segments(route), path),
+                expected.map(|params| params
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), RouteParam::Segment(v.to_string())))
+                    .collect::<RouteParams>())
+            )
+        };
+
+        check_match("/", "/", Some(vec![]));
+        check_match("/users", "/users", Some(vec![]));
+        check_match("/users", "/posts", None);
+        check_match("/users/[id]", "/users/42", Some(vec![("id", "42")]));
+        check_match("/users/[id]", "/users", None);
+        check_match("/users/[id]", "/users/42/posts", None);
+        check_match(
+            "/users/[id]/posts/[post]",
+            "/users/42/posts/hello",
+            Some(vec![("id", "42"), ("post", "hello")]),
+        );
+    }
+
+    #[test]
+    fn route_catch_all_path_matching() {
+        let check_match = |route: &str, path: &str, expected: Option<Vec<&str>>| {
+            assert_eq!(
+                match_segments(&Route::retrieve_segments(route), path),
+                expected.map(|rest| HashMap::from([(
+                    String::from("slug"),
+                    RouteParam::Rest(rest.into_iter().map(String::from).collect())
+                )]))
+            )
+        };
+
+        check_match("/docs/[...slug]", "/docs/intro", Some(vec!["intro"]));
+        check_match("/docs/[...slug]", "/docs/a/b/c", Some(vec!["a", "b", "c"]));
+        check_match("/docs/[...slug]", "/docs", None);
+        check_match("/docs/[...slug]", "/blog/a", None);
+        check_match("/docs/[[...slug]]", "/docs", Some(vec![]));
+        check_match("/docs/[[...slug]]", "/docs/a/b", Some(vec!["a", "b"]));
+        check_match("/[[...slug]]", "/", Some(vec![]));
+        // Catch-all segments are only valid at the end
+        check_match("/[...slug]/edit", "/a/edit", None);
+    }
+
+    #[test]
+    fn route_params_decoding() {
+        let segments = Route::retrieve_segments("/users/[id]");
+        assert_eq!(
+            match_segments(&segments, "/users/John%20Doe"),
+            Some(HashMap::from([(
+                String::from("id"),
+                RouteParam::Segment(String::from("John Doe"))
+            )]))
+        );
+        // Encoded slashes stay in the same segment
+        assert_eq!(
+            match_segments(&segments, "/users/a%2Fb"),
+            Some(HashMap::from([(
+                String::from("id"),
+                RouteParam::Segment(String::from("a/b"))
+            )]))
+        );
+        // Invalid UTF-8 sequences don't match
+        assert_eq!(match_segments(&segments, "/users/%FF"), None);
+
+        let segments = Route::retrieve_segments("/docs/[...slug]");
+        assert_eq!(
+            match_segments(&segments, "/docs/caf%C3%A9/a%20b"),
+            Some(HashMap::from([(
+                String::from("slug"),
+                RouteParam::Rest(vec![String::from("café"), String::from("a b")])
+            )]))
+        );
+        assert_eq!(match_segments(&segments, "/docs/ok/%C3"), None);
+    }
+
+    #[test]
+    fn route_precedence() {
+        let mut routes: Vec<Vec<RouteSegment>> = vec![
+            "/[[...slug]]",
+            "/docs/[...slug]",
+            "/docs/[id]",
+            "/[section]/intro",
+            "/docs/intro",
+            "/docs",
+        ]
+        .into_iter()
+        .map(Route::retrieve_segments)
+        .collect();
+
+        routes.sort_by(|a, b| compare_segments(a, b));
+
+        let check_winner = |path: &str, expected_route: &str| {
+            let winner = routes
+                .iter()
+                .find(|segments| match_segments(segments, path).is_some());
+
+            assert_eq!(winner, Some(&Route::retrieve_segments(expected_route)));
+        };
+
+        // Static routes win over dynamic ones
+        check_winner("/docs/intro", "/docs/intro");
+        check_winner("/docs", "/docs");
+        // Dynamic routes win over catch-all ones
+        check_winner("/docs/setup", "/docs/[id]");
+        check_winner("/guides/intro", "/[section]/intro");
+        // Catch-all routes win over optional catch-all ones
+        check_winner("/docs/setup/linux", "/docs/[...slug]");
+        check_winner("/", "/[[...slug]]");
+        check_winner("/blog/2022/hello", "/[[...slug]]");
+    }
+
+    #[test]
+    fn route_conflict_keys() {
+        let key = |path: &str| route_key(&Route::retrieve_segments(path));
+
+        assert_eq!(key("/"), "/");
+        assert_eq!(key("/api"), "/api");
+        assert_eq!(key("/users/[id]"), key("/users/[name]"));
+        assert_eq!(key("/docs/[...slug]"), key("/docs/[...path]"));
+        assert_ne!(key("/users/[id]"), key("/users/[...id]"));
+        assert_ne!(key("/docs/[...slug]"), key("/docs/[[...slug]]"));
+
+        // Method specific routes
+        let keys = |path: &str, methods: Option<Vec<Method>>| {
+            conflict_keys(
+                RouteKind::Handler,
+                &Route::retrieve_segments(path),
+                &methods,
+            )
+        };
+        let handler = RouteKind::Handler;
+
+        assert_eq!(
+            keys("/api", None),
+            vec![(handler, None, String::from("/api"))]
+        );
+        assert_eq!(
+            keys("/api", Some(vec![Method::GET, Method::POST])),
+            vec![
+                (handler, Some(Method::GET), String::from("/api")),
+                (handler, Some(Method::POST), String::from("/api"))
+            ]
+        );
+
+        // Special handlers don't conflict with the routes in the same folder
+        assert_ne!(
+            conflict_keys(
+                RouteKind::NotFound,
+                &Route::retrieve_segments("/api"),
+                &None
+            ),
+            keys("/api", None)
+        );
+    }
+
+    #[test]
+    fn route_conflict_precedence() {
+        let check_winner = |a: &str, b: &str| {
+            assert_eq!(compare_handlers(Path::new(a), Path::new(b)), Ordering::Less);
+            assert_eq!(
+                compare_handlers(Path::new(b), Path::new(a)),
+                Ordering::Greater
+            );
+        };
+
+        // Named files win over index files
+        check_winner("api.js", "api/index.wasm");
+        check_winner("api.wasm", "api/index.wasm");
+        // Wasm modules win over JavaScript files
+        check_winner("api.wasm", "api.js");
+        check_winner("api/index.wasm", "api/index.js");
+        check_winner("api.get.js", "api/index.get.wasm");
+        // Alphabetical order
+        check_winner("users/[id].js", "users/[name].js");
+    }
+}
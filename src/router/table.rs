@@ -0,0 +1,304 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{match_prefix, route_key, Route, RouteKind, RouteParams, RouteSegment};
+use actix_web::http::Method;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A node of the routes tree. Every node represents a position in the URL path
+/// and stores the routes that finish on it.
+#[derive(Default)]
+struct Node {
+    /// Route that finishes exactly in this node
+    route: Option<usize>,
+    /// Static segments that continue from this node
+    statics: HashMap<String, Node>,
+    /// Dynamic segment that continues from this node. Routes may use different
+    /// names for the same position, so the names are retrieved from the route
+    dynamic: Option<Box<Node>>,
+    /// Catch-all route that captures the rest of the path
+    catch_all: Option<usize>,
+    /// Optional catch-all route that captures the rest of the path, even if it's empty
+    optional_catch_all: Option<usize>,
+}
+
+/// A trie of route segments. Every node is a single segment of the path, as the
+/// segments are not merged like in a radix tree. It finds the route that replies
+/// to a path without checking every route, so the lookup cost depends on the
+/// length of the path instead of the number of routes.
+///
+/// The tree follows the same precedence as the routes list: static segments are
+/// checked first, then dynamic ones and finally catch-all segments.
+#[derive(Default)]
+pub struct RouteTree {
+    root: Node,
+}
+
+impl RouteTree {
+    /// Add the route segments to the tree. The index identifies the route
+    /// (or group of routes) in the caller side. If there's a route with the same segments, the first
+    /// one is kept.
+    pub fn insert(&mut self, segments: &[RouteSegment], index: usize) {
+        let mut node = &mut self.root;
+
+        for (position, segment) in segments.iter().enumerate() {
+            node = match segment {
+                RouteSegment::Static(name) => node.statics.entry(name.clone()).or_default(),
+                RouteSegment::Dynamic(_) => node.dynamic.get_or_insert_with(Box::default).as_mut(),
+                RouteSegment::CatchAll(_) | RouteSegment::OptionalCatchAll(_) => {
+                    // Catch-all segments are only valid at the end of the route
+                    if position == segments.len() - 1 {
+                        let slot = if matches!(segment, RouteSegment::CatchAll(_)) {
+                            &mut node.catch_all
+                        } else {
+                            &mut node.optional_catch_all
+                        };
+                        slot.get_or_insert(index);
+                    }
+
+                    return;
+                }
+            };
+        }
+
+        node.route.get_or_insert(index);
+    }
+
+    /// Find the index of the route (or group of routes) that replies to the given URL path
+    pub fn find(&self, url_path: &str) -> Option<usize> {
+        let url_segments: Vec<&str> = url_path.split('/').filter(|s| !s.is_empty()).collect();
+
+        Self::find_in_node(&self.root, &url_segments)
+    }
+
+    // Look for a route in the given node. It backtracks when a more specific
+    // branch doesn't finish in a route.
+    fn find_in_node(node: &Node, url_segments: &[&str]) -> Option<usize> {
+        match url_segments.split_first() {
+            None => node.route.or(node.optional_catch_all),
+            Some((segment, rest)) => node
+                .statics
+                .get(*segment)
+                .and_then(|child| Self::find_in_node(child, rest))
+                .or_else(|| {
+                    node.dynamic
+                        .as_ref()
+                        .and_then(|child| Self::find_in_node(child, rest))
+                })
+                .or(node.catch_all)
+                .or(node.optional_catch_all),
+        }
+    }
+}
+
+/// The result of looking for the route that replies to a request
+pub enum RouteMatch<'a> {
+    /// The route and the values of its dynamic segments
+    Found(&'a Route, RouteParams),
+    /// There are routes for the URL path, but none of them replies to the
+    /// HTTP method. It includes the allowed methods
+    MethodNotAllowed(Vec<Method>),
+    /// There's no route for the URL path
+    NotFound,
+}
+
+/// The routes of the project. It's built once when the server starts and
+/// resolves the route for every request.
+pub struct RouteTable {
+    /// The list of available routes, sorted by precedence
+    routes: Vec<Route>,
+    /// Routes that reply to the same URL paths. Method specific routes come first
+    groups: Vec<Vec<usize>>,
+    /// A tree to find the route groups by URL path
+    tree: RouteTree,
+    /// Special handlers like `_404`, `_error` and `_middleware`. The ones in deeper folders come first
+    special: Vec<Route>,
+}
+
+impl RouteTable {
+    /// Build the table from the given routes. They must be sorted by precedence.
+    pub fn new(routes: Vec<Route>) -> Self {
+        let (routes, mut special): (Vec<Route>, Vec<Route>) = routes
+            .into_iter()
+            .partition(|route| route.kind == RouteKind::Handler);
+        special.sort_by_key(|route| std::cmp::Reverse(route.segments.len()));
+
+        let mut tree = RouteTree::default();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_keys: HashMap<String, usize> = HashMap::new();
+
+        for (index, route) in routes.iter().enumerate() {
+            let key = route_key(&route.segments);
+
+            match group_keys.get(&key) {
+                Some(group) => groups[*group].push(index),
+                None => {
+                    group_keys.insert(key, groups.len());
+                    tree.insert(&route.segments, groups.len());
+                    groups.push(vec![index]);
+                }
+            }
+        }
+
+        for group in groups.iter_mut() {
+            // Stable sort. It keeps the precedence for routes of the same kind
+            group.sort_by_key(|index| routes[*index].methods.is_none());
+        }
+
+        Self {
+            routes,
+            groups,
+            tree,
+            special,
+        }
+    }
+
+    /// Find the route that replies to the given URL path and HTTP method. The path
+    /// selects the most specific route first, and then the method selects a handler
+    /// of that route. When none of them accepts the method, it returns
+    /// `MethodNotAllowed` without trying less specific routes that match the path,
+    /// like a catch-all route that accepts it.
+    pub fn find(&self, url_path: &str, method: &Method) -> RouteMatch<'_> {
+        let group = match self.tree.find(url_path) {
+            Some(index) => &self.groups[index],
+            None => return RouteMatch::NotFound,
+        };
+
+        let route = group
+            .iter()
+            .map(|index| &self.routes[*index])
+            .find(|route| route.allows(method));
+
+        match route {
+            Some(route) => match route.match_path(url_path) {
+                Some(params) => RouteMatch::Found(route, params),
+                None => RouteMatch::NotFound,
+            },
+            None => {
+                let mut allowed: Vec<Method> = Vec::new();
+
+                for route in group.iter().map(|index| &self.routes[*index]) {
+                    for method in route.methods.iter().flatten() {
+                        if !allowed.contains(method) {
+                            allowed.push(method.clone());
+                        }
+                    }
+                }
+
+                RouteMatch::MethodNotAllowed(allowed)
+            }
+        }
+    }
+
+    /// Find the closest special handler of the given kind for the URL path. It returns
+    /// the handler and the values of the dynamic segments of its folder.
+    pub fn find_special(&self, kind: RouteKind, url_path: &str) -> Option<(&Route, RouteParams)> {
+        self.special
+            .iter()
+            .filter(|route| route.kind == kind)
+            .find_map(|route| match_prefix(&route.segments, url_path).map(|params| (route, params)))
+    }
+
+    /// Find the middlewares that apply to the URL path. The ones in the upper
+    /// folders come first, so they run before the more specific ones.
+    pub fn find_middlewares(&self, url_path: &str) -> Vec<(&Route, RouteParams)> {
+        let mut middlewares: Vec<(&Route, RouteParams)> = self
+            .special
+            .iter()
+            .filter(|route| route.kind == RouteKind::Middleware)
+            .filter_map(|route| {
+                match_prefix(&route.segments, url_path).map(|params| (route, params))
+            })
+            .collect();
+        middlewares.reverse();
+
+        middlewares
+    }
+
+    /// Find the route for the given handler file, including the special handlers
+    pub fn find_handler(&self, handler: &Path) -> Option<&Route> {
+        self.iter().find(|route| route.handler == handler)
+    }
+
+    /// Iterate over the available routes, including the special handlers
+    pub fn iter(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter().chain(self.special.iter())
+    }
+
+    /// Number of available routes, including the special handlers
+    pub fn len(&self) -> usize {
+        self.routes.len() + self.special.len()
+    }
+
+    /// Check if there are no routes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_tree(paths: &[&str]) -> RouteTree {
+        let mut tree = RouteTree::default();
+
+        for (index, path) in paths.iter().enumerate() {
+            tree.insert(&Route::retrieve_segments(path), index);
+        }
+
+        tree
+    }
+
+    #[test]
+    fn route_tree_lookup() {
+        let paths = [
+            "/",
+            "/docs",
+            "/docs/intro",
+            "/docs/[id]",
+            "/docs/[...slug]",
+            "/[section]/intro",
+            "/users/[id]/posts/[post]",
+            "/[[...slug]]",
+        ];
+        let tree = build_tree(&paths);
+        let check_route = |url_path: &str, expected_route: &str| {
+            assert_eq!(
+                tree.find(url_path).map(|index| paths[index]),
+                Some(expected_route)
+            )
+        };
+
+        check_route("/", "/");
+        check_route("/docs", "/docs");
+        check_route("/docs/intro", "/docs/intro");
+        check_route("/docs/setup", "/docs/[id]");
+        check_route("/docs/setup/linux", "/docs/[...slug]");
+        check_route("/guides/intro", "/[section]/intro");
+        check_route("/users/1/posts/hello", "/users/[id]/posts/[post]");
+        // Backtrack when the static branch doesn't finish in a route
+        check_route("/users/intro", "/[section]/intro");
+        check_route("/users/1/posts", "/[[...slug]]");
+        check_route("/blog/2022/hello", "/[[...slug]]");
+    }
+
+    #[test]
+    fn route_tree_without_fallback() {
+        let tree = build_tree(&["/users", "/users/[id]", "/docs/[...slug]"]);
+
+        assert_eq!(tree.find("/users"), Some(0));
+        assert_eq!(tree.find("/users/1"), Some(1));
+        assert_eq!(tree.find("/users/1/posts"), None);
+        assert_eq!(tree.find("/docs"), None);
+        assert_eq!(tree.find("/"), None);
+    }
+
+    #[test]
+    fn route_tree_keeps_first_route() {
+        let tree = build_tree(&["/users/[id]", "/users/[name]"]);
+
+        assert_eq!(tree.find("/users/1"), Some(0));
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+/// Identifier of the custom sections
+const CUSTOM_SECTION: u8 = 0;
+
+/// Identifier of the section with the imports
+const IMPORT_SECTION: u8 = 2;
+
+/// Identifier of the section with the linear memories the module defines
+const MEMORY_SECTION: u8 = 5;
+
+/// Kinds of the imports
+const TABLE_IMPORT: u8 = 1;
+const MEMORY_IMPORT: u8 = 2;
+
+/// Flags of the limits of a table or a memory
+const HAS_MAXIMUM: u8 = 0x01;
+const MEMORY_64: u8 = 0x04;
+
+/// Find the contents of the custom section with the given name. It returns None
+/// for invalid modules and for the text format
+pub fn custom_section<'a>(module: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    sections(module)?
+        .into_iter()
+        .filter(|(id, _)| *id == CUSTOM_SECTION)
+        .find_map(|(_, contents)| {
+            let (length, contents) = read_u32(contents)?;
+
+            if contents.get(..length)? == name.as_bytes() {
+                Some(&contents[length..])
+            } else {
+                None
+            }
+        })
+}
+
+/// The minimum number of pages of every linear memory of the module, including
+/// the imported ones. It returns None for invalid modules and for the text format
+pub fn memory_minimums(module: &[u8]) -> Option<Vec<u64>> {
+    let mut minimums = Vec::new();
+
+    for (id, contents) in sections(module)? {
+        match id {
+            IMPORT_SECTION => {
+                let (count, mut rest) = read_u32(contents)?;
+
+                for _ in 0..count {
+                    // Skip the module and the field names
+                    for _ in 0..2 {
+                        let (length, after) = read_u32(rest)?;
+                        rest = after.get(length..)?;
+                    }
+
+                    let (&kind, after) = rest.split_first()?;
+                    rest = match kind {
+                        TABLE_IMPORT => read_limits(after.get(1..)?)?.1,
+                        MEMORY_IMPORT => {
+                            let (minimum, after) = read_limits(after)?;
+                            minimums.push(minimum);
+                            after
+                        }
+                        // Functions have a type index, and globals a type and a mutability flag
+                        0 => read_u32(after)?.1,
+                        _ => after.get(2..)?,
+                    };
+                }
+            }
+            MEMORY_SECTION => {
+                let (count, mut rest) = read_u32(contents)?;
+
+                for _ in 0..count {
+                    let (minimum, after) = read_limits(rest)?;
+                    minimums.push(minimum);
+                    rest = after;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(minimums)
+}
+
+// Split the module in its sections. It returns None for invalid modules and
+// for the text format
+fn sections(module: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    // Skip the magic number and the binary format version
+    let mut rest = module.strip_prefix(b"\0asm")?.get(4..)?;
+    let mut sections = Vec::new();
+
+    while let Some((&id, after)) = rest.split_first() {
+        let (size, after) = read_u32(after)?;
+        sections.push((id, after.get(..size)?));
+        rest = &after[size..];
+    }
+
+    Some(sections)
+}
+
+// Read the limits of a table or a memory. It returns the minimum and the rest
+// of the bytes
+fn read_limits(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let (&flags, rest) = bytes.split_first()?;
+    let length = if flags & MEMORY_64 != 0 { 10 } else { 5 };
+
+    let (minimum, mut rest) = read_leb128(rest, length)?;
+    if flags & HAS_MAXIMUM != 0 {
+        rest = read_leb128(rest, length)?.1;
+    }
+
+    Some((minimum, rest))
+}
+
+// Read an unsigned LEB128 number. It returns the number and the rest of the bytes
+fn read_u32(bytes: &[u8]) -> Option<(usize, &[u8])> {
+    read_leb128(bytes, 5).map(|(value, rest)| (value as usize, rest))
+}
+
+// Read an unsigned LEB128 number of up to the given number of bytes
+fn read_leb128(bytes: &[u8], length: usize) -> Option<(u64, &[u8])> {
+    let mut result: u64 = 0;
+
+    for (index, byte) in bytes.iter().enumerate().take(length) {
+        result |= ((byte & 0x7f) as u64) << (7 * index);
+
+        if byte & 0x80 == 0 {
+            return Some((result, &bytes[index + 1..]));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A module with the given sections
+    fn module(sections: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+
+        for (id, contents) in sections {
+            module.push(*id);
+            module.push(contents.len() as u8);
+            module.extend_from_slice(contents);
+        }
+
+        module
+    }
+
+    #[test]
+    fn memory_minimums_sections() {
+        // Imports a function, a table with a maximum and a memory of 2 pages
+        let imports = [
+            [3].as_slice(),
+            &[3, b'e', b'n', b'v', 1, b'f', 0, 0],
+            &[3, b'e', b'n', b'v', 1, b't', TABLE_IMPORT, 0x70, 1, 1, 10],
+            &[3, b'e', b'n', b'v', 1, b'm', MEMORY_IMPORT, 0, 2],
+        ]
+        .concat();
+        // Defines a memory of 200 pages with a maximum of 300
+        let memories: &[u8] = &[1, HAS_MAXIMUM, 0xc8, 0x01, 0xac, 0x02];
+
+        assert_eq!(
+            memory_minimums(&module(&[
+                (IMPORT_SECTION, &imports),
+                (MEMORY_SECTION, memories)
+            ])),
+            Some(vec![2, 200])
+        );
+        assert_eq!(memory_minimums(&module(&[])), Some(vec![]));
+        assert_eq!(memory_minimums(b"(module (memory 200))"), None);
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::EngineConfig;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasmtime::{Engine, Module};
+
+/// Version of the Wasmtime crate in the lock file. The build script sets it, as
+/// compiled modules are only valid for the version that compiled them
+const WASMTIME_VERSION: &str = env!("WASMTIME_VERSION");
+
+/// Extension of the compiled modules in the cache folder
+const CACHE_EXTENSION: &str = "cwasm";
+
+/// Ignore file in the cache folder, so version control skips the compiled modules
+const GITIGNORE: (&str, &str) = (".gitignore", "*\n");
+
+/// Stores the compiled modules in a folder, so the next starts don't need to
+/// compile them again. The file name of every module is a hash of its contents,
+/// the wws and Wasmtime versions and the engine settings. A change in any of
+/// them compiles the module again.
+///
+/// The compiled modules are native code, so the cache trusts every file in the
+/// folder. Only the user that runs wws can write to the folders the cache
+/// creates, and the cache ignores the files that other users can modify.
+pub struct ModuleCache {
+    /// Folder that contains the compiled modules
+    folder: PathBuf,
+    /// Identifies the Wasmtime version and the settings that affect the compilation
+    fingerprint: String,
+}
+
+impl ModuleCache {
+    /// Initialize the cache in the given folder. It's created when the first
+    /// module is stored. Modules compiled to count the fuel are stored apart
+    pub fn new(folder: PathBuf, config: &EngineConfig, fuel: bool) -> Self {
+        let fingerprint = format!(
+            "wws-{}:wasmtime-{}:opt-level-{:?}:debug-info-{:?}:pooling-{:?}:fuel-{}",
+            env!("CARGO_PKG_VERSION"),
+            WASMTIME_VERSION,
+            config.opt_level,
+            config.debug_info,
+            config.pooling,
+            fuel
+        );
+
+        Self {
+            folder,
+            fingerprint,
+        }
+    }
+
+    /// Load the compiled module from the cache. If it's not available or it's
+    /// not valid, it compiles the module and stores it for the next time
+    pub fn load(&self, engine: &Engine, bytes: &[u8]) -> Result<Module> {
+        self.load_with(engine, bytes, || Ok(bytes.to_vec()))
+    }
+
+    /// Load the compiled module for the given key from the cache. If it's not
+    /// available, it builds the module with the given function and stores it
+    pub fn load_with<F>(&self, engine: &Engine, key: &[u8], build: F) -> Result<Module>
+    where
+        F: FnOnce() -> Result<Vec<u8>>,
+    {
+        let path = self.path(key);
+
+        if path.exists() && is_private(&self.folder) && is_private(&path) {
+            // Safety: Wasmtime only checks the file header and the engine settings.
+            // It runs the native code in the file as it is, so this assumes nobody
+            // but the wws user wrote it. The cache folder and the file are not
+            // writable by other users, so their contents come from Module::serialize
+            if let Ok(module) = unsafe { Module::deserialize_file(engine, &path) } {
+                return Ok(module);
+            }
+        }
+
+        let module = Module::new(engine, build()?)?;
+        // The cache is an optimization. Failing to write it doesn't prevent
+        // the module from running
+        let _ = self.store(&path, &module);
+
+        Ok(module)
+    }
+
+    // The location of the module for the given key in the cache
+    fn path(&self, key: &[u8]) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(self.fingerprint.as_bytes());
+        hasher.update(key);
+
+        self.folder
+            .join(format!("{:x}", hasher.finalize()))
+            .with_extension(CACHE_EXTENSION)
+    }
+
+    // Write the compiled module. It writes a temporary file first, so other
+    // processes never read a partial module
+    fn store(&self, path: &Path, module: &Module) -> Result<()> {
+        if !self.folder.exists() {
+            create_private_dir(&self.folder)?;
+            fs::write(self.folder.join(GITIGNORE.0), GITIGNORE.1)?;
+        }
+
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp_path, module.serialize()?)?;
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+}
+
+// Create the cache folder. On Unix, only the current user can access it
+fn create_private_dir(folder: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+
+    Ok(builder.create(folder)?)
+}
+
+// Check other users can't modify the given file or folder. On other platforms,
+// the cache relies on the default permissions of the user folders
+#[cfg(unix)]
+fn is_private(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o022 == 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_private(_path: &Path) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE: &[u8] = br#"(module (func (export "_start")))"#;
+
+    #[test]
+    fn module_cache_reuse() {
+        let folder = std::env::temp_dir().join(format!("wws-cache-{}", std::process::id()));
+        let engine = Engine::default();
+        let cache = ModuleCache::new(folder.clone(), &EngineConfig::default(), false);
+
+        cache.load(&engine, MODULE).unwrap();
+        let path = cache.path(MODULE);
+        assert!(path.exists());
+
+        // A cached module loads without compiling it again
+        assert!(cache.load(&engine, MODULE).is_ok());
+
+        // Invalid files are replaced
+        fs::write(&path, b"invalid").unwrap();
+        assert!(cache.load(&engine, MODULE).is_ok());
+        assert_ne!(fs::read(&path).unwrap(), b"invalid");
+
+        // Version control ignores the compiled modules
+        assert_eq!(
+            fs::read_to_string(folder.join(".gitignore")).unwrap(),
+            "*\n"
+        );
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn module_cache_settings() {
+        let config = EngineConfig {
+            debug_info: Some(true),
+            ..EngineConfig::default()
+        };
+        let cache = ModuleCache::new(PathBuf::from("cache"), &EngineConfig::default(), false);
+        let debug_cache = ModuleCache::new(PathBuf::from("cache"), &config, false);
+        let fuel_cache = ModuleCache::new(PathBuf::from("cache"), &EngineConfig::default(), true);
+
+        assert_eq!(cache.path(MODULE), cache.path(MODULE));
+        assert_ne!(cache.path(MODULE), debug_cache.path(MODULE));
+        assert_ne!(cache.path(MODULE), fuel_cache.path(MODULE));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn module_cache_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let folder = std::env::temp_dir().join(format!("wws-cache-perms-{}", std::process::id()));
+        let engine = Engine::default();
+        let cache = ModuleCache::new(folder.clone(), &EngineConfig::default(), false);
+
+        cache.load(&engine, MODULE).unwrap();
+        let mode = fs::metadata(&folder).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // Files that other users can modify are not loaded
+        let path = cache.path(MODULE);
+        assert!(is_private(&path));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o666)).unwrap();
+        assert!(!is_private(&path));
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Seconds the clients should wait before retrying a request when the server is busy
+pub const RETRY_AFTER: u64 = 1;
+
+/// Limits the number of modules that run at the same time. Requests wait in a
+/// queue for a free slot. When the queue is full, new requests are rejected.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    /// Free slots to run a module
+    slots: Arc<Semaphore>,
+    /// Number of requests waiting for a slot
+    waiting: AtomicUsize,
+    /// Maximum number of requests waiting for a slot
+    queue: usize,
+}
+
+/// There are no free slots and the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saturated;
+
+impl fmt::Display for Saturated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "There are too many requests running")
+    }
+}
+
+impl std::error::Error for Saturated {}
+
+impl ConcurrencyLimit {
+    /// Allow the given number of modules to run at the same time, with
+    /// a queue for the given number of requests
+    pub fn new(concurrency: usize, queue: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(concurrency)),
+            waiting: AtomicUsize::new(0),
+            queue,
+        }
+    }
+
+    /// Wait for a free slot. The slot is released when the permit is dropped
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, Saturated> {
+        if let Ok(permit) = Arc::clone(&self.slots).try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let waiting = WaitingGuard::new(&self.waiting);
+        if waiting.position >= self.queue {
+            return Err(Saturated);
+        }
+
+        let permit = Arc::clone(&self.slots).acquire_owned().await;
+        drop(waiting);
+
+        // The semaphore is never closed
+        permit.map_err(|_| Saturated)
+    }
+}
+
+// Counts a request in the queue while it's alive. Requests may be cancelled
+// while waiting, so the guard leaves the queue when it's dropped
+struct WaitingGuard<'a> {
+    waiting: &'a AtomicUsize,
+    /// Number of requests in the queue before this one
+    position: usize,
+}
+
+impl<'a> WaitingGuard<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        Self {
+            position: waiting.fetch_add(1, Ordering::SeqCst),
+            waiting,
+        }
+    }
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn concurrency_limit_queue() {
+        let limit = ConcurrencyLimit::new(1, 1);
+
+        let running = limit.acquire().await.unwrap();
+        let mut queued = Box::pin(limit.acquire());
+        assert!(futures_util::poll!(queued.as_mut()).is_pending());
+
+        // The queue is full
+        assert_eq!(limit.acquire().await.unwrap_err(), Saturated);
+
+        drop(running);
+        assert!(queued.await.is_ok());
+        assert_eq!(limit.waiting.load(Ordering::SeqCst), 0);
+        assert!(limit.acquire().await.is_ok());
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::LimitsConfig;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+use wasmtime::{Engine, ResourceLimiter, StoreLimits, StoreLimitsBuilder};
+
+/// Time between two epoch increments. It defines the precision of the timeouts
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Maximum time to reply when the project doesn't configure it
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of requests waiting to run when the configuration doesn't set it
+pub const DEFAULT_QUEUE: usize = 128;
+
+/// Memory limits are configured in megabytes
+const MEGABYTE: usize = 1024 * 1024;
+
+/// The limits to run a handler. They come from the handler configuration, with
+/// the project configuration as a fallback.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum wall-clock time to reply. It uses the Wasmtime epoch interruption
+    pub timeout: Duration,
+    /// Maximum amount of fuel to consume. Roughly, every Wasm instruction
+    /// consumes one unit of fuel. None means no limit
+    pub fuel: Option<u64>,
+    /// Maximum size of a linear memory in bytes. None means no limit
+    pub memory: Option<usize>,
+    /// Maximum number of elements of a table. None means no limit
+    pub table_elements: Option<u32>,
+    /// Maximum number of instances. None means the Wasmtime default
+    pub instances: Option<usize>,
+    /// Maximum number of tables. None means the Wasmtime default
+    pub tables: Option<usize>,
+    /// Maximum number of linear memories. None means the Wasmtime default
+    pub memories: Option<usize>,
+    /// Maximum number of requests running at the same time. None means no limit
+    pub concurrency: Option<usize>,
+    /// Maximum number of requests waiting to run
+    pub queue: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            fuel: None,
+            memory: None,
+            table_elements: None,
+            instances: None,
+            tables: None,
+            memories: None,
+            concurrency: None,
+            queue: DEFAULT_QUEUE,
+        }
+    }
+}
+
+impl Limits {
+    /// Build the limits from the handler configuration. The project
+    /// configuration provides the values the handler doesn't set
+    pub fn new(handler: Option<&LimitsConfig>, project: &LimitsConfig) -> Self {
+        let handler = handler.cloned().unwrap_or_default();
+
+        Self {
+            timeout: handler
+                .timeout
+                .or(project.timeout)
+                .map(Duration::from_millis)
+                .unwrap_or(DEFAULT_TIMEOUT),
+            fuel: handler.fuel.or(project.fuel),
+            memory: handler
+                .memory
+                .or(project.memory)
+                .map(|megabytes| megabytes as usize * MEGABYTE),
+            table_elements: handler.table_elements.or(project.table_elements),
+            instances: handler.instances.or(project.instances),
+            tables: handler.tables.or(project.tables),
+            memories: handler.memories.or(project.memories),
+            concurrency: handler.concurrency.or(project.concurrency),
+            queue: handler.queue.or(project.queue).unwrap_or(DEFAULT_QUEUE),
+        }
+    }
+
+    /// Number of epoch ticks before interrupting the handler
+    pub fn epoch_deadline(&self) -> u64 {
+        let ticks = self.timeout.as_millis() / EPOCH_TICK.as_millis();
+
+        (ticks as u64).max(1)
+    }
+}
+
+/// The handler reached one of its limits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// It didn't reply in time
+    Timeout(Duration),
+    /// It consumed all the fuel
+    Fuel(u64),
+    /// It tried to grow a memory over the limit, in bytes
+    Memory(usize),
+    /// It tried to grow a table over the limit
+    TableElements(u32),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(timeout) => {
+                write!(f, "The handler didn't reply in {}ms", timeout.as_millis())
+            }
+            Self::Fuel(fuel) => write!(f, "The handler consumed all its fuel ({})", fuel),
+            Self::Memory(memory) => write!(
+                f,
+                "The handler exceeded its memory limit ({}MB)",
+                memory / MEGABYTE
+            ),
+            Self::TableElements(elements) => write!(
+                f,
+                "The handler exceeded its table limit ({} elements)",
+                elements
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Applies the resource limits to a store. It keeps the first violation, so the
+/// runner can report it instead of the trap that it causes
+pub struct ResourceTracker {
+    /// The limits to apply
+    limits: StoreLimits,
+    /// Configured memory limit in bytes
+    memory: Option<usize>,
+    /// Configured table limit
+    table_elements: Option<u32>,
+    /// The first limit the module exceeded
+    pub exceeded: Option<LimitExceeded>,
+}
+
+impl ResourceTracker {
+    /// Build the tracker for the given limits
+    pub fn new(limits: &Limits) -> Self {
+        let mut builder = StoreLimitsBuilder::new();
+
+        if let Some(memory) = limits.memory {
+            builder = builder.memory_size(memory);
+        }
+        if let Some(elements) = limits.table_elements {
+            builder = builder.table_elements(elements);
+        }
+        if let Some(instances) = limits.instances {
+            builder = builder.instances(instances);
+        }
+        if let Some(tables) = limits.tables {
+            builder = builder.tables(tables);
+        }
+        if let Some(memories) = limits.memories {
+            builder = builder.memories(memories);
+        }
+
+        Self {
+            limits: builder.build(),
+            memory: limits.memory,
+            table_elements: limits.table_elements,
+            exceeded: None,
+        }
+    }
+}
+
+impl ResourceLimiter for ResourceTracker {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool {
+        let allowed = self.limits.memory_growing(current, desired, maximum);
+
+        if !allowed && self.exceeded.is_none() {
+            if let Some(memory) = self.memory.filter(|memory| desired > *memory) {
+                self.exceeded = Some(LimitExceeded::Memory(memory));
+            }
+        }
+
+        allowed
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+        let allowed = self.limits.table_growing(current, desired, maximum);
+
+        if !allowed && self.exceeded.is_none() {
+            if let Some(elements) = self.table_elements.filter(|elements| desired > *elements) {
+                self.exceeded = Some(LimitExceeded::TableElements(elements));
+            }
+        }
+
+        allowed
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
+/// Increment the epoch of the engine periodically in a different thread. Running
+/// handlers are interrupted when the epoch reaches their deadline
+pub fn start_epoch_ticker(engine: Engine) {
+    thread::spawn(move || loop {
+        thread::sleep(EPOCH_TICK);
+        engine.increment_epoch();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_fallback() {
+        let handler = LimitsConfig {
+            timeout: Some(500),
+            memory: Some(16),
+            ..LimitsConfig::default()
+        };
+        let project = LimitsConfig {
+            timeout: Some(2000),
+            fuel: Some(1_000_000),
+            memory: Some(64),
+            instances: Some(2),
+            ..LimitsConfig::default()
+        };
+
+        assert_eq!(
+            Limits::new(Some(&handler), &project),
+            Limits {
+                timeout: Duration::from_millis(500),
+                fuel: Some(1_000_000),
+                memory: Some(16 * MEGABYTE),
+                instances: Some(2),
+                ..Limits::default()
+            }
+        );
+        assert_eq!(
+            Limits::new(None, &project).timeout,
+            Duration::from_millis(2000)
+        );
+        assert_eq!(
+            Limits::new(None, &LimitsConfig::default()),
+            Limits::default()
+        );
+    }
+
+    #[test]
+    fn limits_epoch_deadline() {
+        let limits = |timeout: u64| Limits {
+            timeout: Duration::from_millis(timeout),
+            ..Limits::default()
+        };
+
+        assert_eq!(limits(1000).epoch_deadline(), 100);
+        assert_eq!(limits(15).epoch_deadline(), 1);
+        assert_eq!(limits(0).epoch_deadline(), 1);
+    }
+
+    #[test]
+    fn resource_tracker_violations() {
+        let mut tracker = ResourceTracker::new(&Limits {
+            memory: Some(MEGABYTE),
+            table_elements: Some(10),
+            ..Limits::default()
+        });
+
+        assert!(tracker.memory_growing(0, MEGABYTE, None));
+        assert!(tracker.exceeded.is_none());
+        assert!(!tracker.memory_growing(MEGABYTE, 2 * MEGABYTE, None));
+        assert_eq!(tracker.exceeded, Some(LimitExceeded::Memory(MEGABYTE)));
+        // It keeps the first violation
+        assert!(!tracker.table_growing(0, 20, None));
+        assert_eq!(tracker.exceeded, Some(LimitExceeded::Memory(MEGABYTE)));
+    }
+}
@@ -0,0 +1,1248 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+mod binary;
+mod cache;
+mod concurrency;
+mod limits;
+mod pool;
+mod protocol;
+mod runtime;
+mod snapshot;
+
+pub use concurrency::{ConcurrencyLimit, Saturated, RETRY_AFTER};
+pub use limits::{start_epoch_ticker, LimitExceeded, Limits, DEFAULT_QUEUE};
+pub use pool::{PoolMetrics, PoolSize};
+pub use protocol::Protocol;
+pub use runtime::Runtime;
+
+use limits::ResourceTracker;
+
+use crate::router::RouteParams;
+use actix_web::{
+    http::{
+        header::{self, HeaderMap},
+        StatusCode, Uri,
+    },
+    web, HttpMessage, HttpRequest,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wasi_common::{pipe::ReadPipe, pipe::WritePipe};
+use wasmtime::*;
+use wasmtime_wasi::{sync::WasiCtxBuilder, WasiCtx};
+
+/// JSON input for wasm modules. This information is passed via STDIN / WASI
+/// to the module.
+#[derive(Serialize, Deserialize)]
+pub struct WasmInput {
+    /// Request full URL
+    url: String,
+    /// Request method
+    method: String,
+    /// Parameters of the URL query. Repeated parameters keep all their values in order
+    query: HashMap<String, Vec<String>>,
+    /// Cookies of the request
+    cookies: HashMap<String, String>,
+    /// Address of the client if available
+    remote_addr: Option<String>,
+    /// Scheme of the request (http or https)
+    scheme: String,
+    /// Host of the request, including the port if it's present
+    host: String,
+    /// HTTP version of the request, like "HTTP/1.1"
+    http_version: String,
+    /// Unique identifier of the request. It's the same for the middlewares and
+    /// the handler
+    request_id: String,
+    /// Request headers. The values of repeated headers are joined
+    headers: HashMap<String, String>,
+    /// Request headers as a list of name and value pairs. Repeated headers
+    /// appear several times, keeping the order of their values
+    raw_headers: Vec<(String, String)>,
+    /// Request body. Binary bodies are encoded in base64
+    body: String,
+    /// Encoding of the request body
+    body_encoding: BodyEncoding,
+    /// Key / Value store content if available
+    kv: HashMap<String, String>,
+    /// Values of the dynamic and catch-all segments of the route
+    params: RouteParams,
+    /// Details of the failure. Only available for not found and error handlers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<WasmError>,
+    /// Extra information added by the middlewares
+    context: HashMap<String, String>,
+}
+
+/// Encoding of the request and response bodies in the JSON messages. Text bodies
+/// keep their contents, while binary ones are encoded in base64.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    /// UTF-8 text
+    #[default]
+    Utf8,
+    /// Binary content encoded in base64
+    Base64,
+}
+
+impl BodyEncoding {
+    /// Encode the given body. Valid UTF-8 bodies are sent as text
+    pub fn encode(body: &[u8]) -> (String, Self) {
+        match std::str::from_utf8(body) {
+            Ok(text) => (String::from(text), Self::Utf8),
+            Err(_) => (base64::encode(body), Self::Base64),
+        }
+    }
+
+    /// Decode the given body
+    pub fn decode(&self, body: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Utf8 => Ok(body.as_bytes().to_vec()),
+            Self::Base64 => Ok(base64::decode(body)?),
+        }
+    }
+}
+
+/// The values of a header in the module output. Modules set a list of values
+/// for the headers that appear several times, like `Set-Cookie`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum HeaderValues {
+    /// A single value
+    One(String),
+    /// Multiple values in order
+    Many(Vec<String>),
+}
+
+impl HeaderValues {
+    /// Iterate over the values
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        let values = match self {
+            Self::One(value) => std::slice::from_ref(value),
+            Self::Many(values) => values.as_slice(),
+        };
+
+        values.iter().map(|value| value.as_str())
+    }
+}
+
+/// The changes that middlewares apply to the request. Every stage receives the
+/// request with the changes of the previous ones.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct RequestChanges {
+    /// New request URL
+    #[serde(default)]
+    pub url: Option<String>,
+    /// New request headers. They replace the original ones
+    #[serde(default)]
+    pub headers: Option<HashMap<String, HeaderValues>>,
+    /// Extra information for the next stages
+    #[serde(default)]
+    pub context: HashMap<String, String>,
+}
+
+impl RequestChanges {
+    /// Apply the changes from a later stage
+    pub fn merge(&mut self, changes: RequestChanges) {
+        if changes.url.is_some() {
+            self.url = changes.url;
+        }
+        if changes.headers.is_some() {
+            self.headers = changes.headers;
+        }
+        self.context.extend(changes.context);
+    }
+
+    /// Returns the URL path of the request if a middleware changed it
+    pub fn path(&self) -> Option<String> {
+        let uri = self.url.as_ref()?.parse::<Uri>().ok()?;
+
+        Some(uri.path().to_string())
+    }
+}
+
+/// Details of a failure that are passed to the not found (`_404`) and
+/// error (`_error`) handlers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WasmError {
+    /// The HTTP status of the failure
+    pub status: u16,
+    /// A description of the failure
+    pub message: String,
+    /// The original URL path of the request
+    pub path: String,
+}
+
+impl WasmError {
+    /// Describe a failure for the given URL path
+    pub fn new(status: StatusCode, message: &str, path: &str) -> Self {
+        Self {
+            status: status.as_u16(),
+            message: message.to_string(),
+            path: path.to_string(),
+        }
+    }
+}
+
+/// Marks the server as running behind a trusted reverse proxy. When the app data
+/// contains it, the handlers receive the scheme and the host from the `Forwarded`
+/// and `X-Forwarded-*` headers. Otherwise, any client could set them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrustedProxy;
+
+impl WasmInput {
+    /// Generates a new struct to pass the data to wasm module. It's based on the
+    /// HttpRequest, body, the Key / Value store (if available), the route params,
+    /// the failure details for not found and error handlers and the changes
+    /// applied by the middlewares
+    pub fn new(
+        request: &HttpRequest,
+        body: &[u8],
+        kv: Option<HashMap<String, String>>,
+        params: RouteParams,
+        error: Option<WasmError>,
+        changes: &RequestChanges,
+    ) -> Self {
+        let (body, body_encoding) = BodyEncoding::encode(body);
+        let url = changes
+            .url
+            .clone()
+            .unwrap_or_else(|| request.uri().to_string());
+        let raw_headers = match &changes.headers {
+            Some(headers) => headers
+                .iter()
+                .flat_map(|(name, values)| {
+                    values
+                        .iter()
+                        .map(move |value| (name.clone(), String::from(value)))
+                })
+                .collect(),
+            None => build_headers_list(request.headers()),
+        };
+
+        let (scheme, host) = connection_details(request);
+
+        Self {
+            query: parse_query(&url),
+            url,
+            method: String::from(request.method().as_str()),
+            cookies: parse_cookies(&raw_headers),
+            remote_addr: request.peer_addr().map(|addr| addr.to_string()),
+            scheme,
+            host,
+            http_version: format!("{:?}", request.version()),
+            request_id: request_id(request),
+            headers: join_headers(&raw_headers),
+            raw_headers,
+            body,
+            body_encoding,
+            kv: kv.unwrap_or_default(),
+            params,
+            error,
+            context: changes.context.clone(),
+        }
+    }
+}
+
+/// JSON output from a wasm module. This information is passed via STDOUT / WASI
+/// from the module.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WasmOutput {
+    /// Response body. Binary bodies are encoded in base64
+    pub body: String,
+    /// Encoding of the response body
+    #[serde(default)]
+    pub body_encoding: BodyEncoding,
+    /// Response headers. Repeated headers contain a list of values
+    pub headers: HashMap<String, HeaderValues>,
+    /// Response HTTP status
+    pub status: u16,
+    /// New state of the K/V store if available
+    pub kv: HashMap<String, String>,
+}
+
+impl WasmOutput {
+    /// Decode the response body
+    pub fn body(&self) -> Result<Vec<u8>> {
+        self.body_encoding.decode(&self.body)
+    }
+}
+
+/// JSON output from a middleware. It either replies to the request or passes
+/// the request to the next stage with some changes.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum MiddlewareOutput {
+    /// Continue with the next middleware or handler
+    Next {
+        /// The changes to apply to the request
+        request: RequestChanges,
+        /// New state of the K/V store if available
+        #[serde(default)]
+        kv: Option<HashMap<String, String>>,
+    },
+    /// Reply to the request, skipping the next stages
+    Response(WasmOutput),
+}
+
+/// Builds the JSON string to pass to the Wasm module using WASI STDIO strategy.
+/// The input follows the protocol version of the module.
+pub fn build_wasm_input(
+    request: &HttpRequest,
+    body: &[u8],
+    kv: Option<HashMap<String, String>>,
+    params: RouteParams,
+    error: Option<WasmError>,
+    changes: &RequestChanges,
+    protocol: Protocol,
+) -> String {
+    protocol.input_json(&WasmInput::new(request, body, kv, params, error, changes))
+}
+
+// The scheme and the host of the request. The proxy headers only apply when
+// the server runs behind a trusted proxy
+fn connection_details(request: &HttpRequest) -> (String, String) {
+    if request.app_data::<web::Data<TrustedProxy>>().is_some() {
+        // The connection info lives in the request extensions. It's released
+        // before storing the request identifier
+        let connection = request.connection_info();
+
+        return (
+            String::from(connection.scheme()),
+            String::from(connection.host()),
+        );
+    }
+
+    let config = request.app_config();
+    let scheme = match request.uri().scheme_str() {
+        Some(scheme) => scheme,
+        None if config.secure() => "https",
+        None => "http",
+    };
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| {
+            request
+                .uri()
+                .authority()
+                .map(|authority| authority.as_str())
+        })
+        .unwrap_or_else(|| config.host());
+
+    (String::from(scheme), String::from(host))
+}
+
+/// Create a list of name and value pairs from a HeaderMap. Values that
+/// are not valid UTF-8 are converted lossily
+pub fn build_headers_list(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(key, value)| {
+            (
+                String::from(key.as_str()),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Create HashMap from a list of headers. The values of repeated headers
+/// are joined with commas, except cookies that use semicolons
+pub fn join_headers(headers: &[(String, String)]) -> HashMap<String, String> {
+    let mut joined: HashMap<String, String> = HashMap::new();
+
+    for (key, value) in headers.iter() {
+        match joined.get_mut(key) {
+            Some(current) => {
+                let separator = if key.eq_ignore_ascii_case("cookie") {
+                    "; "
+                } else {
+                    ", "
+                };
+                current.push_str(separator);
+                current.push_str(value);
+            }
+            None => {
+                joined.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    joined
+}
+
+/// Parse the query parameters of the given URL. Repeated parameters keep all
+/// their values in order
+pub fn parse_query(url: &str) -> HashMap<String, Vec<String>> {
+    let mut query: HashMap<String, Vec<String>> = HashMap::new();
+    let query_string = url
+        .parse::<Uri>()
+        .ok()
+        .and_then(|uri| uri.query().map(String::from))
+        .unwrap_or_default();
+
+    if let Ok(params) = web::Query::<Vec<(String, String)>>::from_query(&query_string) {
+        for (key, value) in params.into_inner() {
+            query.entry(key).or_default().push(value);
+        }
+    }
+
+    query
+}
+
+/// Parse the cookies from the `Cookie` headers. When a cookie appears several
+/// times, the first value wins
+pub fn parse_cookies(headers: &[(String, String)]) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+
+    for (_, value) in headers
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("cookie"))
+    {
+        for pair in value.split(';') {
+            if let Some((name, value)) = pair.split_once('=') {
+                let name = name.trim();
+
+                if !name.is_empty() {
+                    cookies
+                        .entry(String::from(name))
+                        .or_insert_with(|| String::from(value.trim()));
+                }
+            }
+        }
+    }
+
+    cookies
+}
+
+/// Identifies a request in all its stages
+#[derive(Clone)]
+struct RequestId(String);
+
+/// Count of the generated request identifiers
+static REQUEST_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// Get the identifier of the request. The first stage generates it and stores
+// it in the request, so the next stages receive the same one
+fn request_id(request: &HttpRequest) -> String {
+    if let Some(RequestId(id)) = request.extensions().get::<RequestId>() {
+        return id.clone();
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or_default();
+    let id = format!(
+        "{:012x}-{:04x}-{:08x}",
+        timestamp,
+        std::process::id() & 0xffff,
+        REQUEST_COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    id
+}
+
+/// Argument to run the QuickJS engine in compile mode. It reads the handler
+/// source from STDIN and writes the bytecode to STDOUT
+const JS_COMPILE_FLAG: &str = "--compile";
+
+/// The data of the store for every run. It contains the WASI context
+/// and the tracker of the resources the module uses
+struct StoreState {
+    wasi: WasiCtx,
+    resources: ResourceTracker,
+}
+
+#[derive(Clone)]
+pub enum RunnerHandlerType {
+    Wasm,
+    /// The QuickJS engine. It receives the handler source with every request
+    JavaScript,
+    /// A snapshot of the QuickJS engine with the handler source already evaluated
+    JavaScriptSnapshot,
+}
+
+/// A runner is composed by a Wasmtime engine instance and a pre-instantiated
+/// wasm module. The module imports are already resolved, so every run only
+/// creates a store and instantiates it.
+#[derive(Clone)]
+pub struct Runner {
+    /// Engine that runs the actual Wasm module
+    engine: Engine,
+    /// The type of the required runner
+    runner_type: RunnerHandlerType,
+    /// Preloaded Module with its imports
+    instance_pre: InstancePre<StoreState>,
+    /// QuickJS bytecode of the handler if required. It's shared by the runner clones
+    bytecode: Arc<[u8]>,
+    /// Last modification of the handler file when the module was loaded
+    modified: Option<SystemTime>,
+    /// Running instances of all the runners
+    metrics: Arc<PoolMetrics>,
+    /// Protocol version the module declares if any
+    declared_protocol: Option<Protocol>,
+    /// Protocol version of the input and output messages
+    protocol: Protocol,
+    /// The engine counts the fuel the module consumes
+    fuel: bool,
+}
+
+impl Runner {
+    /// Creates a Runner. It will preload the module from the given wasm file.
+    /// JavaScript handlers use a snapshot of the QuickJS engine with their source
+    /// already evaluated. If the engine doesn't support snapshots, they share the
+    /// QuickJS engine of the runtime and load the handler bytecode on every request.
+    /// The protocol version comes from the module or the QuickJS engine.
+    pub fn new(runtime: &Runtime, path: &PathBuf) -> Result<Self> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        if !Self::is_js_file(path) {
+            let bytes = fs::read(path)?;
+            let declared_protocol = Protocol::declared(&bytes)?;
+            let module = runtime.compile(&bytes)?;
+            let mut runner = Self::from_module(runtime, RunnerHandlerType::Wasm, &module)?;
+            runner.modified = modified;
+            runner.set_declared_protocol(declared_protocol);
+
+            return Ok(runner);
+        }
+
+        let source = fs::read_to_string(path)?;
+        let mut runner = match runtime.js_snapshot(&source)? {
+            Some(module) => {
+                Self::from_module(runtime, RunnerHandlerType::JavaScriptSnapshot, &module)?
+            }
+            None => {
+                let module = runtime.js_engine()?;
+                let mut runner =
+                    Self::from_module(runtime, RunnerHandlerType::JavaScript, &module)?;
+                runner.bytecode = runner.compile_js(&source)?.into();
+
+                runner
+            }
+        };
+        runner.modified = modified;
+        runner.set_declared_protocol(runtime.js_protocol()?);
+
+        Ok(runner)
+    }
+
+    // Prepare a runner for the given module
+    fn from_module(
+        runtime: &Runtime,
+        runner_type: RunnerHandlerType,
+        module: &Module,
+    ) -> Result<Self> {
+        Ok(Self {
+            engine: runtime.engine().clone(),
+            runner_type,
+            instance_pre: runtime.instantiate_pre(module)?,
+            bytecode: Arc::from(Vec::new()),
+            modified: None,
+            metrics: Arc::clone(runtime.metrics()),
+            declared_protocol: None,
+            protocol: Protocol::default(),
+            fuel: runtime.fuel(),
+        })
+    }
+
+    // Store the protocol the module declares and use it for the messages
+    fn set_declared_protocol(&mut self, declared: Option<Protocol>) {
+        self.declared_protocol = declared;
+        self.set_fallback_protocol(None);
+    }
+
+    /// Set the protocol version for the modules that don't declare one, like
+    /// the version of the handler configuration. Without both, the runner uses
+    /// the version 1
+    pub fn set_fallback_protocol(&mut self, fallback: Option<Protocol>) {
+        self.protocol = self.declared_protocol.or(fallback).unwrap_or_default();
+    }
+
+    /// The protocol version of the input and output messages
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    // Compile the given source with the QuickJS engine. The engine includes the
+    // polyfill in the bytecode
+    fn compile_js(&self, source: &str) -> Result<Vec<u8>> {
+        let args = [String::from("wws"), String::from(JS_COMPILE_FLAG)];
+
+        self.execute_with_args(source.as_bytes().to_vec(), Some(&args), &Limits::default())
+    }
+
+    /// Check if the engine can apply a fuel limit to the module
+    pub fn supports_fuel(&self) -> bool {
+        self.fuel
+    }
+
+    /// Check if the handler file changed after loading the module
+    pub fn is_outdated(&self, path: &Path) -> bool {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+
+        modified.is_none() || modified != self.modified
+    }
+
+    /// Check if the given file is a JavaScript handler
+    pub fn is_js_file(path: &Path) -> bool {
+        match path.extension() {
+            Some(os_str) => os_str == "js",
+            None => false,
+        }
+    }
+
+    /// Run the wasm module. To inject the data, it already receives the JSON input
+    /// from the WasmInput serialization. It initializes a new WASI context with
+    /// the required pipes. Then, it sends the data and read the output from the wasm
+    /// run. When the module reaches one of the limits, it returns a LimitExceeded error.
+    pub fn run(&self, input: &str, limits: &Limits) -> Result<WasmOutput> {
+        let mut output: WasmOutput = serde_json::from_slice(&self.execute(input, limits)?)?;
+        self.restore_header_names(&mut output.headers);
+
+        Ok(output)
+    }
+
+    /// Run the wasm module as a middleware. It follows the same approach as `run`,
+    /// but the module may return the changes for the request instead of a response.
+    pub fn run_middleware(&self, input: &str, limits: &Limits) -> Result<MiddlewareOutput> {
+        let mut output: MiddlewareOutput = serde_json::from_slice(&self.execute(input, limits)?)?;
+
+        match &mut output {
+            MiddlewareOutput::Next { request, .. } => {
+                if let Some(headers) = &mut request.headers {
+                    self.restore_header_names(headers);
+                }
+            }
+            MiddlewareOutput::Response(response) => {
+                self.restore_header_names(&mut response.headers)
+            }
+        }
+
+        Ok(output)
+    }
+
+    // Compatibility shim for JavaScript handlers. The QuickJS engines of the
+    // protocol version 1 convert the property names of the output to snake case,
+    // so the "-" characters of the header names come as "_". Other modules keep
+    // their header names as they are
+    fn restore_header_names(&self, headers: &mut HashMap<String, HeaderValues>) {
+        if self.protocol == Protocol::V1 && !matches!(self.runner_type, RunnerHandlerType::Wasm) {
+            protocol::restore_header_names(headers);
+        }
+    }
+
+    // Run the module with the given input and return the raw output. The QuickJS
+    // engine receives the length of the handler bytecode, the bytecode and the input
+    fn execute(&self, input: &str, limits: &Limits) -> Result<Vec<u8>> {
+        let stdin = match self.runner_type {
+            RunnerHandlerType::Wasm | RunnerHandlerType::JavaScriptSnapshot => {
+                input.as_bytes().to_vec()
+            }
+            RunnerHandlerType::JavaScript => {
+                let mut contents = Vec::with_capacity(4 + self.bytecode.len() + input.len());
+                contents.extend_from_slice(&(self.bytecode.len() as u32).to_le_bytes());
+                contents.extend_from_slice(&self.bytecode);
+                contents.extend_from_slice(input.as_bytes());
+
+                contents
+            }
+        };
+
+        self.execute_with_args(stdin, None, limits)
+    }
+
+    // Run the module with the given STDIN contents and return the raw output. The
+    // module receives the given arguments or the server ones if there are none
+    fn execute_with_args(
+        &self,
+        stdin: Vec<u8>,
+        args: Option<&[String]>,
+        limits: &Limits,
+    ) -> Result<Vec<u8>> {
+        let stdin = ReadPipe::from(stdin);
+        let stdout = WritePipe::new_in_memory();
+        let stderr = WritePipe::new_in_memory();
+
+        // WASI context
+        let builder = WasiCtxBuilder::new()
+            .stdin(Box::new(stdin.clone()))
+            .stdout(Box::new(stdout.clone()))
+            .stderr(Box::new(stderr.clone()));
+        let wasi = match args {
+            Some(args) => builder.args(args)?,
+            None => builder.inherit_args()?,
+        }
+        .build();
+        let state = StoreState {
+            wasi,
+            resources: ResourceTracker::new(limits),
+        };
+        let _instance = self.metrics.track();
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.resources);
+        store.set_epoch_deadline(limits.epoch_deadline());
+        if self.fuel {
+            store.add_fuel(limits.fuel.unwrap_or(u64::MAX))?;
+        }
+
+        if let Err(err) = self.call(&mut store) {
+            return Err(Self::limit_error(err, &store, limits));
+        }
+
+        drop(store);
+
+        let contents: Vec<u8> = stdout
+            .try_into_inner()
+            .map_err(|_err| anyhow::Error::msg("Nothing to show"))?
+            .into_inner();
+
+        Ok(contents)
+    }
+
+    // Instantiate the module and call its default export
+    fn call(&self, store: &mut Store<StoreState>) -> Result<()> {
+        let instance = self.instance_pre.instantiate(&mut *store)?;
+        let start = instance
+            .get_func(&mut *store, "")
+            .or_else(|| instance.get_func(&mut *store, "_start"))
+            .ok_or_else(|| anyhow::Error::msg("The module doesn't export a _start function"))?;
+
+        start.typed::<(), (), _>(&*store)?.call(&mut *store, ())?;
+
+        Ok(())
+    }
+
+    // Check if the module failed because it reached one of the limits
+    fn limit_error(
+        err: anyhow::Error,
+        store: &Store<StoreState>,
+        limits: &Limits,
+    ) -> anyhow::Error {
+        if let Some(exceeded) = &store.data().resources.exceeded {
+            return exceeded.clone().into();
+        }
+
+        let trap_code = err.downcast_ref::<Trap>().and_then(|trap| trap.trap_code());
+        if trap_code == Some(TrapCode::Interrupt) {
+            return LimitExceeded::Timeout(limits.timeout).into();
+        }
+
+        if let (Some(fuel), Some(consumed)) = (limits.fuel, store.fuel_consumed()) {
+            if consumed >= fuel {
+                return LimitExceeded::Fuel(fuel).into();
+            }
+        }
+
+        err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::router::RouteParam;
+    use actix_web::test::TestRequest;
+    use std::sync::OnceLock;
+
+    // Output with header names that contain "_" and "-" characters
+    const HEADERS_OUTPUT: &str = r#"{"body":"","headers":{"X_Custom_Id":"1","X-Request-Id":"2","content-type":"text/plain"},"status":200,"kv":{}}"#;
+
+    // A JavaScript handler that sets header names with "_" and "-" characters
+    const HEADERS_HANDLER: &str = r#"
+        addEventListener("fetch", (event) => {
+          const response = new Response("Hello");
+          response.headers.set("X-Custom-Id", "1");
+          response.headers.set("X_Trace", "2");
+
+          return event.respondWith(response);
+        });
+    "#;
+
+    // A module that writes the given output to STDOUT and ignores the input
+    fn output_module(output: &str) -> String {
+        format!(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write"
+                    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "{}")
+                (func (export "_start")
+                    (i32.store (i32.const 0) (i32.const 16))
+                    (i32.store (i32.const 4) (i32.const {}))
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#,
+            output.replace('"', "\\\""),
+            output.len()
+        )
+    }
+
+    // Run the output module with the given runner type and protocol version
+    fn run_output(runner_type: RunnerHandlerType, protocol: Protocol, output: &str) -> WasmOutput {
+        let config = EngineConfig {
+            cache: Some(false),
+            ..EngineConfig::default()
+        };
+        let runtime = Runtime::new(&config, Path::new("."), None, false).unwrap();
+        let module = Module::new(runtime.engine(), output_module(output)).unwrap();
+
+        let mut runner = Runner::from_module(&runtime, runner_type, &module).unwrap();
+        runner.set_fallback_protocol(Some(protocol));
+
+        runner.run("{}", &Limits::default()).unwrap()
+    }
+
+    // Load a JavaScript handler with the given source. The tests share the
+    // runtime, so they only compile the engine once
+    fn js_runner(name: &str, source: &str) -> Runner {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+        let runtime = RUNTIME.get_or_init(|| {
+            let config = EngineConfig {
+                cache: Some(false),
+                ..EngineConfig::default()
+            };
+            Runtime::new(&config, Path::new("."), None, false).unwrap()
+        });
+
+        let folder = std::env::temp_dir().join(format!("wws-js-{}-{}", name, std::process::id()));
+        let path = folder.join("handler.js");
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(&path, source).unwrap();
+
+        let runner = Runner::new(runtime, &path).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+
+        runner
+    }
+
+    // The input of a JavaScript handler for the given request
+    fn js_input(
+        runner: &Runner,
+        request: &HttpRequest,
+        body: &[u8],
+        params: RouteParams,
+        error: Option<WasmError>,
+    ) -> String {
+        build_wasm_input(
+            request,
+            body,
+            None,
+            params,
+            error,
+            &RequestChanges::default(),
+            runner.protocol(),
+        )
+    }
+
+    #[test]
+    fn request_changes_merge() {
+        let mut changes = RequestChanges::default();
+        changes.merge(RequestChanges {
+            url: Some(String::from("/docs?lang=en")),
+            headers: None,
+            context: HashMap::from([(String::from("user"), String::from("admin"))]),
+        });
+        changes.merge(RequestChanges {
+            url: None,
+            headers: Some(HashMap::from([(
+                String::from("accept"),
+                HeaderValues::One(String::from("text/html")),
+            )])),
+            context: HashMap::from([(String::from("role"), String::from("owner"))]),
+        });
+
+        assert_eq!(changes.path(), Some(String::from("/docs")));
+        assert_eq!(changes.headers.unwrap().len(), 1);
+        assert_eq!(changes.context.len(), 2);
+    }
+
+    #[test]
+    fn middleware_output_parsing() {
+        let next: MiddlewareOutput =
+            serde_json::from_str(r#"{"request":{"url":"/new","context":{"a":"b"}},"kv":{}}"#)
+                .unwrap();
+        let response: MiddlewareOutput =
+            serde_json::from_str(r#"{"body":"Unauthorized","headers":{},"status":401,"kv":{}}"#)
+                .unwrap();
+
+        assert!(matches!(
+            next,
+            MiddlewareOutput::Next { request, .. } if request.url == Some(String::from("/new"))
+        ));
+        assert!(matches!(
+            response,
+            MiddlewareOutput::Response(output) if output.status == 401
+        ));
+    }
+
+    #[test]
+    fn body_encoding() {
+        assert_eq!(
+            BodyEncoding::encode("Hello ⚙️".as_bytes()),
+            (String::from("Hello ⚙️"), BodyEncoding::Utf8)
+        );
+        assert_eq!(
+            BodyEncoding::encode(&[0x89, 0x50, 0x4e, 0x47]),
+            (String::from("iVBORw=="), BodyEncoding::Base64)
+        );
+
+        let output: WasmOutput = serde_json::from_str(
+            r#"{"body":"iVBORw==","body_encoding":"base64","headers":{},"status":200,"kv":{}}"#,
+        )
+        .unwrap();
+        assert_eq!(output.body().unwrap(), vec![0x89, 0x50, 0x4e, 0x47]);
+
+        // Modules that don't set the encoding return text
+        let output: WasmOutput =
+            serde_json::from_str(r#"{"body":"Hi","headers":{},"status":200,"kv":{}}"#).unwrap();
+        assert_eq!(output.body().unwrap(), b"Hi".to_vec());
+    }
+
+    #[test]
+    fn multi_value_headers() {
+        let headers = vec![
+            (String::from("accept"), String::from("text/html")),
+            (String::from("accept"), String::from("application/json")),
+            (String::from("cookie"), String::from("a=1")),
+            (String::from("cookie"), String::from("b=2")),
+        ];
+        let joined = join_headers(&headers);
+
+        assert_eq!(joined["accept"], "text/html, application/json");
+        assert_eq!(joined["cookie"], "a=1; b=2");
+
+        let output: WasmOutput = serde_json::from_str(
+            r#"{"body":"","headers":{"set-cookie":["a=1","b=2"],"vary":"Accept"},"status":200,"kv":{}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            output.headers["set-cookie"].iter().collect::<Vec<&str>>(),
+            vec!["a=1", "b=2"]
+        );
+        assert_eq!(
+            output.headers["vary"].iter().collect::<Vec<&str>>(),
+            vec!["Accept"]
+        );
+    }
+
+    #[test]
+    fn wasm_header_names() {
+        for protocol in [Protocol::V1, Protocol::V2] {
+            let output = run_output(RunnerHandlerType::Wasm, protocol, HEADERS_OUTPUT);
+            let mut names: Vec<&str> = output.headers.keys().map(|name| name.as_str()).collect();
+            names.sort_unstable();
+
+            // The names keep their case and their characters
+            assert_eq!(names, vec!["X-Request-Id", "X_Custom_Id", "content-type"]);
+        }
+    }
+
+    #[test]
+    fn js_handler_header_names() {
+        let runner = js_runner("headers", HEADERS_HANDLER);
+        let request = TestRequest::default().to_http_request();
+        let output = runner
+            .run(
+                &js_input(&runner, &request, b"", RouteParams::new(), None),
+                &Limits::default(),
+            )
+            .unwrap();
+        let mut names: Vec<&str> = output.headers.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+
+        // The Headers object lower-cases the names, but the "_" characters stay
+        assert!(names.contains(&"x-custom-id"));
+        assert!(names.contains(&"x_trace"));
+    }
+
+    #[test]
+    fn js_header_names() {
+        for runner_type in [
+            RunnerHandlerType::JavaScript,
+            RunnerHandlerType::JavaScriptSnapshot,
+        ] {
+            // The engines of the version 1 replace the "-" characters
+            let output = run_output(runner_type.clone(), Protocol::V1, HEADERS_OUTPUT);
+            assert!(output.headers.contains_key("X-Custom-Id"));
+            assert!(output.headers.contains_key("content-type"));
+            assert!(!output.headers.contains_key("X_Custom_Id"));
+
+            let output = run_output(runner_type, Protocol::V2, HEADERS_OUTPUT);
+            assert!(output.headers.contains_key("X_Custom_Id"));
+        }
+    }
+
+    #[test]
+    fn js_handler_params() {
+        let runner = js_runner(
+            "params",
+            r#"
+            addEventListener("fetch", (event) => {
+              const { id, path } = event.request.params;
+              return event.respondWith(new Response(`${id} ${path.join("/")}`));
+            });
+            "#,
+        );
+        let params = RouteParams::from([
+            (String::from("id"), RouteParam::Segment(String::from("a b"))),
+            (
+                String::from("path"),
+                RouteParam::Rest(vec![String::from("docs"), String::from("intro")]),
+            ),
+        ]);
+        let request = TestRequest::default().to_http_request();
+        let output = runner
+            .run(
+                &js_input(&runner, &request, b"", params, None),
+                &Limits::default(),
+            )
+            .unwrap();
+
+        assert_eq!(output.body().unwrap(), b"a b docs/intro".to_vec());
+    }
+
+    #[test]
+    fn js_handler_error() {
+        let runner = js_runner(
+            "error",
+            r#"
+            addEventListener("fetch", (event) => {
+              const { status, message, path } = event.error;
+              return event.respondWith(new Response(`${message} at ${path}`, { status }));
+            });
+            "#,
+        );
+        let error = WasmError::new(StatusCode::NOT_FOUND, "Not found", "/missing");
+        let request = TestRequest::default().to_http_request();
+        let input = js_input(&runner, &request, b"", RouteParams::new(), Some(error));
+        let output = runner.run(&input, &Limits::default()).unwrap();
+
+        assert_eq!(output.status, 404);
+        assert_eq!(output.body().unwrap(), b"Not found at /missing".to_vec());
+    }
+
+    #[test]
+    fn js_middleware() {
+        let runner = js_runner(
+            "middleware",
+            r#"
+            addEventListener("fetch", (event) => {
+              if (event.request.headers.get("authorization") === null) {
+                return event.respondWith(new Response("Unauthorized", { status: 401 }));
+              }
+
+              event.request.headers.set("x-user", "admin");
+              return event.next({ user: "admin" });
+            });
+            "#,
+        );
+
+        let request = TestRequest::default().to_http_request();
+        let input = js_input(&runner, &request, b"", RouteParams::new(), None);
+        let output = runner.run_middleware(&input, &Limits::default()).unwrap();
+        assert!(matches!(
+            output,
+            MiddlewareOutput::Response(output) if output.status == 401
+        ));
+
+        let request = TestRequest::default()
+            .uri("/admin")
+            .insert_header(("authorization", "Bearer token"))
+            .to_http_request();
+        let input = js_input(&runner, &request, b"", RouteParams::new(), None);
+        let output = runner.run_middleware(&input, &Limits::default()).unwrap();
+        match output {
+            MiddlewareOutput::Next { request, .. } => {
+                assert_eq!(request.url, Some(String::from("/admin")));
+                assert_eq!(request.context["user"], "admin");
+                assert!(request.headers.unwrap().contains_key("x-user"));
+            }
+            MiddlewareOutput::Response(_) => panic!("The middleware didn't call next()"),
+        }
+    }
+
+    #[test]
+    fn js_handler_binary_body() {
+        let runner = js_runner(
+            "binary",
+            r#"
+            addEventListener("fetch", (event) => {
+              const bytes = new Uint8Array(event.request.arrayBuffer()).reverse();
+              return event.respondWith(new Response(bytes));
+            });
+            "#,
+        );
+        let request = TestRequest::post().to_http_request();
+        let input = js_input(
+            &runner,
+            &request,
+            &[0x89, 0x50, 0x4e, 0x47, 0xff],
+            RouteParams::new(),
+            None,
+        );
+        let output = runner.run(&input, &Limits::default()).unwrap();
+
+        assert_eq!(output.body_encoding, BodyEncoding::Base64);
+        assert_eq!(output.body().unwrap(), vec![0xff, 0x47, 0x4e, 0x50, 0x89]);
+    }
+
+    #[test]
+    fn js_handler_multi_value_headers() {
+        let runner = js_runner(
+            "multi-value",
+            r#"
+            addEventListener("fetch", (event) => {
+              const response = new Response(event.request.headers.get("accept"));
+              response.headers.append("Set-Cookie", "a=1");
+              response.headers.append("Set-Cookie", "b=2");
+
+              return event.respondWith(response);
+            });
+            "#,
+        );
+        let request = TestRequest::default()
+            .append_header(("accept", "text/html"))
+            .append_header(("accept", "application/json"))
+            .to_http_request();
+        let output = runner
+            .run(
+                &js_input(&runner, &request, b"", RouteParams::new(), None),
+                &Limits::default(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            output.body().unwrap(),
+            b"text/html, application/json".to_vec()
+        );
+        assert_eq!(
+            output.headers["set-cookie"].iter().collect::<Vec<&str>>(),
+            vec!["a=1", "b=2"]
+        );
+    }
+
+    #[test]
+    fn js_handler_request_details() {
+        let runner = js_runner(
+            "request-details",
+            r#"
+            addEventListener("fetch", (event) => {
+              const { query, cookies, fullUrl } = event.request;
+              const body = [
+                query.getAll("tag").join(","),
+                cookies.session,
+                fullUrl.hostname,
+                fullUrl.searchParams.get("q"),
+              ].join(" ");
+
+              return event.respondWith(new Response(body));
+            });
+            "#,
+        );
+        let request = TestRequest::default()
+            .uri("/search?q=wasm&tag=a&tag=b%20c")
+            .insert_header(("host", "example.com"))
+            .insert_header(("cookie", "session=abc; theme=dark"))
+            .to_http_request();
+        let output = runner
+            .run(
+                &js_input(&runner, &request, b"", RouteParams::new(), None),
+                &Limits::default(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            output.body().unwrap(),
+            b"a,b c abc example.com wasm".to_vec()
+        );
+    }
+
+    #[test]
+    fn request_details() {
+        let request = actix_web::test::TestRequest::default()
+            .uri("/search?q=wasm&tag=a&tag=b%20c")
+            .insert_header(("host", "example.com"))
+            .insert_header(("cookie", "session=abc; theme=dark"))
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .to_http_request();
+        let input = WasmInput::new(
+            &request,
+            b"",
+            None,
+            RouteParams::new(),
+            None,
+            &RequestChanges::default(),
+        );
+
+        assert_eq!(input.query["q"], vec!["wasm"]);
+        assert_eq!(input.query["tag"], vec!["a", "b c"]);
+        assert_eq!(input.cookies["session"], "abc");
+        assert_eq!(input.cookies["theme"], "dark");
+        assert_eq!(input.remote_addr.as_deref(), Some("127.0.0.1:4000"));
+        assert_eq!(input.scheme, "http");
+        assert_eq!(input.host, "example.com");
+        assert_eq!(input.http_version, "HTTP/1.1");
+
+        // The middlewares and the handler receive the same identifier
+        let changes = RequestChanges {
+            url: Some(String::from("/search?page=2")),
+            ..RequestChanges::default()
+        };
+        let next = WasmInput::new(&request, b"", None, RouteParams::new(), None, &changes);
+        assert_eq!(next.request_id, input.request_id);
+        assert_eq!(next.query["page"], vec!["2"]);
+        assert!(!next.query.contains_key("q"));
+
+        let other = actix_web::test::TestRequest::default().to_http_request();
+        let other = WasmInput::new(&other, b"", None, RouteParams::new(), None, &changes);
+        assert_ne!(other.request_id, input.request_id);
+    }
+
+    #[test]
+    fn request_details_behind_proxy() {
+        let request = |proxy: bool| {
+            let mut request = TestRequest::default()
+                .insert_header(("host", "127.0.0.1:8080"))
+                .insert_header(("x-forwarded-proto", "https"))
+                .insert_header(("x-forwarded-host", "example.com"));
+
+            if proxy {
+                request = request.app_data(web::Data::new(TrustedProxy));
+            }
+
+            let request = request.to_http_request();
+            let input = WasmInput::new(
+                &request,
+                b"",
+                None,
+                RouteParams::new(),
+                None,
+                &RequestChanges::default(),
+            );
+
+            (input.scheme, input.host)
+        };
+
+        // Clients can't change the details without a trusted proxy
+        assert_eq!(
+            request(false),
+            (String::from("http"), String::from("127.0.0.1:8080"))
+        );
+        assert_eq!(
+            request(true),
+            (String::from("https"), String::from("example.com"))
+        );
+    }
+}
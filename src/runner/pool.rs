@@ -0,0 +1,327 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::binary::memory_minimums;
+use crate::config::LimitsConfig;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wasmtime::{InstanceAllocationStrategy, InstanceLimits, PoolingAllocationStrategy};
+
+/// Size of a Wasm page in bytes
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// Maximum number of pages of a 32-bit linear memory (4 GiB)
+const MAX_MEMORY_PAGES: u64 = 65536;
+
+/// Pages of every memory slot when no handler limits the memory (10 MiB). Every
+/// slot reserves this memory, so it fits the QuickJS engine and small modules
+const DEFAULT_MEMORY_PAGES: u64 = 160;
+
+/// Elements of every table slot when no handler limits them. The function table
+/// of the QuickJS engine has around 1000 elements
+const DEFAULT_TABLE_ELEMENTS: u32 = 10_000;
+
+/// Tables and memories of every instance. The QuickJS engine and the modules
+/// of the kits define one of each
+const DEFAULT_TABLES: u32 = 1;
+const DEFAULT_MEMORIES: u32 = 1;
+
+/// Slots for the instances that run while loading the handlers, like the QuickJS
+/// engine compiling a handler. Handlers load one by one, so they need one slot
+const LOADING_SLOTS: usize = 1;
+
+/// Memory limits are configured in megabytes
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Size of the pooling allocator. Every running handler takes one instance slot,
+/// so the pool never needs more slots than modules running at the same time.
+/// The slots fit the largest limits of the project and the handlers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolSize {
+    /// Number of instance slots for the handlers
+    pub instances: usize,
+    /// Maximum number of pages of every linear memory
+    pub memory_pages: u64,
+    /// Maximum number of elements of every table
+    pub table_elements: u32,
+    /// Maximum number of tables of every instance
+    pub tables: u32,
+    /// Maximum number of linear memories of every instance
+    pub memories: u32,
+}
+
+impl PoolSize {
+    /// Calculate the pool for the given number of routes and concurrency. When
+    /// the project limits the concurrency of every handler, the routes may need
+    /// less slots than the server concurrency. The largest memory and table limits
+    /// of the project and the handlers set the size of the slots.
+    pub fn new(
+        routes: usize,
+        concurrency: usize,
+        limits: &LimitsConfig,
+        handler_limits: &[LimitsConfig],
+    ) -> Self {
+        let route_concurrency = limits.concurrency.unwrap_or(concurrency);
+        let instances = concurrency
+            .min(routes.saturating_mul(route_concurrency))
+            .max(1);
+        let all_limits = || std::iter::once(limits).chain(handler_limits.iter());
+
+        let memory_pages = all_limits()
+            .filter_map(|limits| limits.memory)
+            .max()
+            .map(|megabytes| (megabytes * MEGABYTE / WASM_PAGE_SIZE).min(MAX_MEMORY_PAGES))
+            .unwrap_or(DEFAULT_MEMORY_PAGES);
+        let table_elements = all_limits()
+            .filter_map(|limits| limits.table_elements)
+            .max()
+            .unwrap_or(DEFAULT_TABLE_ELEMENTS);
+        let tables = all_limits()
+            .filter_map(|limits| limits.tables)
+            .fold(DEFAULT_TABLES, |tables, value| tables.max(value as u32));
+        let memories = all_limits()
+            .filter_map(|limits| limits.memories)
+            .fold(DEFAULT_MEMORIES, |memories, value| {
+                memories.max(value as u32)
+            });
+
+        Self {
+            instances,
+            memory_pages,
+            table_elements,
+            tables,
+            memories,
+        }
+    }
+
+    /// Memory of every slot in megabytes
+    pub fn memory_megabytes(&self) -> u64 {
+        self.memory_pages * WASM_PAGE_SIZE / MEGABYTE
+    }
+
+    /// Check the linear memories of the given module fit in the slots. Wasmtime
+    /// can't instantiate a module that starts with more memory than a slot has
+    pub fn check_module(&self, module: &[u8]) -> Result<(), MemorySlotExceeded> {
+        let minimums = memory_minimums(module).unwrap_or_default();
+
+        match minimums.into_iter().max() {
+            Some(pages) if pages > self.memory_pages => Err(MemorySlotExceeded {
+                required: pages * WASM_PAGE_SIZE,
+                available: self.memory_pages * WASM_PAGE_SIZE,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The Wasmtime allocation strategy for this pool. It includes the slots
+    /// to load the handlers
+    pub fn strategy(&self) -> InstanceAllocationStrategy {
+        let instance_limits = InstanceLimits {
+            count: (self.instances + LOADING_SLOTS) as u32,
+            memory_pages: self.memory_pages,
+            table_elements: self.table_elements,
+            tables: self.tables,
+            memories: self.memories,
+            ..InstanceLimits::default()
+        };
+
+        InstanceAllocationStrategy::Pooling {
+            strategy: PoolingAllocationStrategy::NextAvailable,
+            instance_limits,
+        }
+    }
+}
+
+/// The initial memory of a module doesn't fit in the slots of the pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemorySlotExceeded {
+    /// Initial memory of the module in bytes
+    pub required: u64,
+    /// Memory of every slot in bytes
+    pub available: u64,
+}
+
+impl fmt::Display for MemorySlotExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The module requires {}MB of memory, but the instance pool slots have {}MB. Increase the memory limit to use larger slots",
+            (self.required + MEGABYTE - 1) / MEGABYTE,
+            self.available / MEGABYTE
+        )
+    }
+}
+
+impl std::error::Error for MemorySlotExceeded {}
+
+/// Tracks the instances that are running. With the pooling allocator, it shows
+/// the utilization of the pool.
+#[derive(Debug, Default)]
+pub struct PoolMetrics {
+    /// Number of instance slots. None when the pooling allocator is disabled
+    size: Option<usize>,
+    /// Instances running now
+    in_use: AtomicUsize,
+    /// Maximum number of instances running at the same time
+    peak: AtomicUsize,
+    /// Instances created since the server started
+    total: AtomicUsize,
+}
+
+impl PoolMetrics {
+    /// Initialize the metrics for the given pool
+    pub fn new(pool: Option<PoolSize>) -> Self {
+        Self {
+            size: pool.map(|pool| pool.instances),
+            ..Self::default()
+        }
+    }
+
+    /// Count a new running instance. It finishes when the guard is dropped
+    pub fn track(&self) -> InstanceGuard<'_> {
+        let in_use = self.in_use.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(in_use, Ordering::SeqCst);
+        self.total.fetch_add(1, Ordering::SeqCst);
+
+        InstanceGuard { metrics: self }
+    }
+
+    /// Format the metrics using the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: usize| {
+            let _ = writeln!(output, "# HELP {} {}", name, help);
+            let _ = writeln!(output, "# TYPE {} {}", name, kind);
+            let _ = writeln!(output, "{} {}", name, value);
+        };
+
+        if let Some(size) = self.size {
+            metric(
+                "wws_pool_instances",
+                "gauge",
+                "Instance slots in the pooling allocator",
+                size,
+            );
+        }
+
+        metric(
+            "wws_instances_in_use",
+            "gauge",
+            "Instances running now",
+            self.in_use.load(Ordering::SeqCst),
+        );
+        metric(
+            "wws_instances_peak",
+            "gauge",
+            "Maximum number of instances running at the same time",
+            self.peak.load(Ordering::SeqCst),
+        );
+        metric(
+            "wws_instances_total",
+            "counter",
+            "Instances created since the server started",
+            self.total.load(Ordering::SeqCst),
+        );
+
+        output
+    }
+}
+
+/// A running instance. It leaves the pool when it's dropped
+pub struct InstanceGuard<'a> {
+    metrics: &'a PoolMetrics,
+}
+
+impl Drop for InstanceGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.in_use.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_size_from_routes() {
+        let limits = LimitsConfig::default();
+        assert_eq!(PoolSize::new(10, 8, &limits, &[]).instances, 8);
+        assert_eq!(PoolSize::new(0, 8, &limits, &[]).instances, 1);
+
+        let pool = PoolSize::new(1, 8, &limits, &[]);
+        assert_eq!(pool.memory_pages, DEFAULT_MEMORY_PAGES);
+        assert_eq!(pool.table_elements, DEFAULT_TABLE_ELEMENTS);
+        assert_eq!((pool.tables, pool.memories), (1, 1));
+
+        let limits = LimitsConfig {
+            concurrency: Some(2),
+            memory: Some(64),
+            ..LimitsConfig::default()
+        };
+        let pool = PoolSize::new(3, 16, &limits, &[]);
+        assert_eq!(pool.instances, 6);
+        assert_eq!(pool.memory_pages, 1024);
+    }
+
+    #[test]
+    fn pool_size_from_handler_limits() {
+        let limits = LimitsConfig {
+            memory: Some(16),
+            ..LimitsConfig::default()
+        };
+        let handler_limits = [
+            LimitsConfig {
+                memory: Some(128),
+                table_elements: Some(20_000),
+                ..LimitsConfig::default()
+            },
+            LimitsConfig {
+                memory: Some(32),
+                tables: Some(2),
+                ..LimitsConfig::default()
+            },
+        ];
+
+        let pool = PoolSize::new(2, 4, &limits, &handler_limits);
+        assert_eq!(pool.memory_pages, 2048);
+        assert_eq!(pool.table_elements, 20_000);
+        assert_eq!((pool.tables, pool.memories), (2, 1));
+        assert_eq!(pool.memory_megabytes(), 128);
+    }
+
+    #[test]
+    fn pool_size_module_memory() {
+        let pool = PoolSize::new(1, 1, &LimitsConfig::default(), &[]);
+        assert_eq!(pool.memory_megabytes(), 10);
+
+        // A module with a memory of 160 pages and a module with 161 pages
+        let module =
+            |pages: u8| [b"\0asm\x01\0\0\0".as_slice(), &[5, 4, 1, 0, pages, 0x01]].concat();
+
+        assert!(pool.check_module(&module(0xa0)).is_ok());
+        assert_eq!(
+            pool.check_module(&module(0xa1)),
+            Err(MemorySlotExceeded {
+                required: 161 * WASM_PAGE_SIZE,
+                available: 160 * WASM_PAGE_SIZE,
+            })
+        );
+    }
+
+    #[test]
+    fn pool_metrics_utilization() {
+        let metrics = PoolMetrics::new(Some(PoolSize::new(4, 4, &LimitsConfig::default(), &[])));
+
+        let first = metrics.track();
+        let second = metrics.track();
+        drop(first);
+        drop(second);
+        let _third = metrics.track();
+
+        let output = metrics.render();
+        assert!(output.contains("wws_pool_instances 4\n"));
+        assert!(output.contains("wws_instances_in_use 1\n"));
+        assert!(output.contains("wws_instances_peak 2\n"));
+        assert!(output.contains("wws_instances_total 3\n"));
+    }
+}
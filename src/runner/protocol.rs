@@ -0,0 +1,238 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::binary::custom_section;
+use super::{BodyEncoding, HeaderValues, WasmError, WasmInput};
+use crate::router::RouteParams;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Name of the custom section that declares the protocol version of a module.
+/// It contains the version as a decimal string, like "2"
+pub const PROTOCOL_SECTION: &str = "wws_protocol";
+
+/// Version of the JSON messages between wws and the modules. Modules declare
+/// the version they understand, so new fields don't break the ones compiled
+/// for a previous version.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// The original messages with the fields that extend them without changing
+    /// their format: the route params, the error details and the middleware
+    /// context. The body is always text, the request headers are joined and the
+    /// QuickJS engine replaces the "-" characters of the response header names
+    /// with "_". Modules that don't declare a version use it
+    #[default]
+    V1,
+    /// Binary bodies in base64, repeated headers, the parsed query, the cookies
+    /// and the client details. Header names keep their original characters
+    V2,
+}
+
+impl Protocol {
+    /// The most recent version wws supports
+    pub const LATEST: Self = Self::V2;
+
+    /// Parse the given version number
+    pub fn parse(version: &str) -> Option<Self> {
+        match version.trim() {
+            "1" => Some(Self::V1),
+            "2" => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    /// The version number
+    pub fn version(&self) -> u32 {
+        match self {
+            Self::V1 => 1,
+            Self::V2 => 2,
+        }
+    }
+
+    /// Read the version the given module declares in its custom section. It fails
+    /// when the module requires a version wws doesn't support
+    pub fn declared(module: &[u8]) -> Result<Option<Self>> {
+        let section = match custom_section(module, PROTOCOL_SECTION) {
+            Some(section) => String::from_utf8_lossy(section),
+            None => return Ok(None),
+        };
+
+        match Self::parse(&section) {
+            Some(protocol) => Ok(Some(protocol)),
+            None => Err(anyhow::Error::msg(format!(
+                "The module requires the protocol version {}, but the latest supported one is {}",
+                section.trim(),
+                Self::LATEST.version()
+            ))),
+        }
+    }
+
+    /// Serialize the input of a module
+    pub fn input_json(&self, input: &WasmInput) -> String {
+        match self {
+            Self::V1 => serde_json::to_string(&InputV1::from(input)).unwrap(),
+            Self::V2 => serde_json::to_string(input).unwrap(),
+        }
+    }
+}
+
+/// The input of the modules that use the version 1, with its extensions
+#[derive(Serialize)]
+struct InputV1<'a> {
+    url: &'a str,
+    method: &'a str,
+    headers: &'a HashMap<String, String>,
+    /// Binary bodies are converted to text lossily
+    body: String,
+    kv: &'a HashMap<String, String>,
+    params: &'a RouteParams,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a WasmError>,
+    context: &'a HashMap<String, String>,
+}
+
+impl<'a> From<&'a WasmInput> for InputV1<'a> {
+    fn from(input: &'a WasmInput) -> Self {
+        let body = match input.body_encoding {
+            BodyEncoding::Utf8 => input.body.clone(),
+            BodyEncoding::Base64 => {
+                let bytes = input.body_encoding.decode(&input.body).unwrap_or_default();
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+        };
+
+        Self {
+            url: &input.url,
+            method: &input.method,
+            headers: &input.headers,
+            body,
+            kv: &input.kv,
+            params: &input.params,
+            error: input.error.as_ref(),
+            context: &input.context,
+        }
+    }
+}
+
+/// Restore the "-" characters of the header names in the output of the QuickJS
+/// engines that use the version 1
+pub fn restore_header_names(headers: &mut HashMap<String, HeaderValues>) {
+    *headers = std::mem::take(headers)
+        .into_iter()
+        .map(|(name, values)| (name.replace('_', "-"), values))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{RequestChanges, WasmOutput};
+    use actix_web::test::TestRequest;
+    use serde_json::Value;
+
+    // A request with every detail of the input
+    fn golden_input() -> WasmInput {
+        let request = TestRequest::post()
+            .uri("/search?q=wasm&tag=a&tag=b")
+            .insert_header(("host", "localhost:8080"))
+            .insert_header(("accept", "text/html"))
+            .insert_header(("cookie", "session=abc"))
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .to_http_request();
+        let mut input = WasmInput::new(
+            &request,
+            &[0xff, 0x00, 0x61],
+            None,
+            RouteParams::new(),
+            None,
+            &RequestChanges::default(),
+        );
+        // The generated identifier changes on every run and the order of
+        // the headers depends on the request map
+        input.request_id = String::from("golden");
+        input.raw_headers.sort();
+
+        input
+    }
+
+    fn fixture(contents: &str) -> Value {
+        serde_json::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn protocol_input_v1_extended_golden() {
+        let input: Value = serde_json::from_str(&Protocol::V1.input_json(&golden_input())).unwrap();
+
+        assert_eq!(
+            input,
+            fixture(include_str!("fixtures/protocol/v1/input_extended.json"))
+        );
+    }
+
+    #[test]
+    fn protocol_input_v2_golden() {
+        let input: Value = serde_json::from_str(&Protocol::V2.input_json(&golden_input())).unwrap();
+
+        assert_eq!(
+            input,
+            fixture(include_str!("fixtures/protocol/v2/input.json"))
+        );
+    }
+
+    #[test]
+    fn protocol_output_v1_golden() {
+        let mut output: WasmOutput =
+            serde_json::from_str(include_str!("fixtures/protocol/v1/output.json")).unwrap();
+        restore_header_names(&mut output.headers);
+
+        assert_eq!(output.body().unwrap(), b"Hello".to_vec());
+        assert_eq!(
+            output.headers["x-generated-by"],
+            HeaderValues::One(String::from("wasm-workers-server"))
+        );
+    }
+
+    #[test]
+    fn protocol_output_v2_golden() {
+        let output: WasmOutput =
+            serde_json::from_str(include_str!("fixtures/protocol/v2/output.json")).unwrap();
+
+        assert_eq!(output.body().unwrap(), vec![0xff, 0x00, 0x61]);
+        assert_eq!(
+            output.headers["set-cookie"],
+            HeaderValues::Many(vec![String::from("a=1"), String::from("b=2")])
+        );
+        assert!(output.headers.contains_key("X_Custom_Id"));
+    }
+
+    #[test]
+    fn protocol_declared_section() {
+        // A module with the protocol section and an empty type section
+        let module = |version: &[u8]| {
+            let mut section = vec![PROTOCOL_SECTION.len() as u8];
+            section.extend_from_slice(PROTOCOL_SECTION.as_bytes());
+            section.extend_from_slice(version);
+
+            let mut module = b"\0asm\x01\0\0\0".to_vec();
+            module.extend_from_slice(&[1, 1, 0, 0, section.len() as u8]);
+            module.extend_from_slice(&section);
+            module
+        };
+
+        assert_eq!(
+            Protocol::declared(&module(b"2")).unwrap(),
+            Some(Protocol::V2)
+        );
+        assert_eq!(
+            Protocol::declared(&module(b"1")).unwrap(),
+            Some(Protocol::V1)
+        );
+        assert!(Protocol::declared(&module(b"99")).is_err());
+        assert_eq!(
+            Protocol::declared(br#"(module (func (export "_start")))"#).unwrap(),
+            None
+        );
+        assert_eq!(Protocol::declared(b"\0asm\x01\0\0\0").unwrap(), None);
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::cache::ModuleCache;
+use super::limits::{Limits, ResourceTracker};
+use super::pool::{PoolMetrics, PoolSize};
+use super::protocol::Protocol;
+use super::snapshot;
+use super::StoreState;
+use crate::config::{EngineConfig, OptLevel};
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use wasmtime::{Config, Engine, InstancePre, Linker, Module, Store};
+use wasmtime_wasi::sync::WasiCtxBuilder;
+
+// Load the QuickJS compiled engine from kits/javascript
+static JS_ENGINE_WASM: &[u8] =
+    include_bytes!("../../kits/javascript/wasm-workers-quick-js-engine.wasm");
+
+/// The Wasm runtime shared by all the runners. It contains the engine that
+/// compiles and runs the modules and a linker with WASI already defined.
+/// Runners pre-instantiate their modules with it, so every request only
+/// creates a store and instantiates the module. The compiled modules are
+/// stored in the project cache folder when it's enabled. With a pool size,
+/// the engine allocates the instances from a pool instead of on demand.
+#[derive(Clone)]
+pub struct Runtime {
+    /// Engine to compile and run the modules
+    engine: Engine,
+    /// Linker with the WASI imports
+    linker: Arc<Linker<StoreState>>,
+    /// The QuickJS engine. It's compiled once for all the JavaScript handlers,
+    /// the first time a project contains one
+    js_engine: Arc<Mutex<Option<Module>>>,
+    /// Compiled modules from previous runs
+    cache: Option<Arc<ModuleCache>>,
+    /// Running instances and pool utilization
+    metrics: Arc<PoolMetrics>,
+    /// Size of the pool when the engine allocates the instances from it
+    pool: Option<PoolSize>,
+    /// The engine counts the fuel the modules consume
+    fuel: bool,
+}
+
+impl Runtime {
+    /// Build the runtime with the given engine settings. The engine always supports
+    /// epoch interruption, so the runners can limit the execution time of the
+    /// handlers. Counting the fuel slows down the modules, so the engine only
+    /// does it when a handler has a fuel limit.
+    pub fn new(
+        config: &EngineConfig,
+        base_path: &Path,
+        pool: Option<PoolSize>,
+        fuel: bool,
+    ) -> Result<Self> {
+        let engine = Engine::new(&build_config(config, pool, fuel))?;
+
+        let mut linker: Linker<StoreState> = Linker::new(&engine);
+        wasmtime_wasi::add_to_linker(&mut linker, |s| &mut s.wasi)?;
+
+        Ok(Self {
+            engine,
+            linker: Arc::new(linker),
+            js_engine: Arc::new(Mutex::new(None)),
+            cache: config
+                .cache_folder(base_path)
+                .map(|folder| Arc::new(ModuleCache::new(folder, config, fuel))),
+            metrics: Arc::new(PoolMetrics::new(pool)),
+            pool,
+            fuel,
+        })
+    }
+
+    /// The engine that compiles and runs the modules
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Check if the engine counts the fuel the modules consume
+    pub fn fuel(&self) -> bool {
+        self.fuel
+    }
+
+    /// The running instances and the utilization of the pool
+    pub fn metrics(&self) -> &Arc<PoolMetrics> {
+        &self.metrics
+    }
+
+    // Returns the compiled QuickJS engine. It compiles it the first time
+    pub(super) fn js_engine(&self) -> Result<Module> {
+        let mut js_engine = self.js_engine.lock().unwrap();
+
+        if let Some(module) = js_engine.as_ref() {
+            return Ok(module.clone());
+        }
+
+        let module = self.compile(JS_ENGINE_WASM)?;
+        *js_engine = Some(module.clone());
+
+        Ok(module)
+    }
+
+    // Returns the protocol version the QuickJS engine declares if any
+    pub(super) fn js_protocol(&self) -> Result<Option<Protocol>> {
+        Protocol::declared(JS_ENGINE_WASM)
+    }
+
+    // Returns the QuickJS engine with the given handler source already evaluated.
+    // When the engine doesn't support snapshots or the Wizer CLI is not installed,
+    // it returns None
+    pub(super) fn js_snapshot(&self, source: &str) -> Result<Option<Module>> {
+        if self
+            .js_engine()?
+            .get_export(snapshot::INIT_FUNCTION)
+            .is_none()
+            || !snapshot::is_available()
+        {
+            return Ok(None);
+        }
+
+        let build = || {
+            let module = snapshot::initialize(JS_ENGINE_WASM, source)?;
+            self.check_pool(&module)?;
+
+            Ok(module)
+        };
+        let module = match &self.cache {
+            Some(cache) => {
+                // The snapshot depends on the engine too
+                let key = format!(
+                    "{}:{}:{}",
+                    snapshot::INIT_FUNCTION,
+                    js_engine_hash(),
+                    source
+                );
+                cache.load_with(&self.engine, key.as_bytes(), build)?
+            }
+            None => Module::new(&self.engine, build()?)?,
+        };
+
+        Ok(Some(module))
+    }
+
+    // Compile the given module. It loads it from the cache when it's available
+    pub(super) fn compile(&self, bytes: &[u8]) -> Result<Module> {
+        self.check_pool(bytes)?;
+
+        match &self.cache {
+            Some(cache) => cache.load(&self.engine, bytes),
+            None => Module::new(&self.engine, bytes),
+        }
+    }
+
+    // Check the initial memory of the given module fits in the pool slots
+    fn check_pool(&self, bytes: &[u8]) -> Result<()> {
+        match &self.pool {
+            Some(pool) => Ok(pool.check_module(bytes)?),
+            None => Ok(()),
+        }
+    }
+
+    // Resolve the imports of the given module. The result instantiates the module
+    // in new stores without looking up the imports again
+    pub(super) fn instantiate_pre(&self, module: &Module) -> Result<InstancePre<StoreState>> {
+        // The store is only required to type-check the imports
+        let state = StoreState {
+            wasi: WasiCtxBuilder::new().build(),
+            resources: ResourceTracker::new(&Limits::default()),
+        };
+        let mut store = Store::new(&self.engine, state);
+
+        self.linker.instantiate_pre(&mut store, module)
+    }
+}
+
+// Hash of the embedded QuickJS engine. It's calculated once
+fn js_engine_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+
+    HASH.get_or_init(|| format!("{:x}", Sha256::digest(JS_ENGINE_WASM)))
+}
+
+// Build the Wasmtime configuration from the project settings
+fn build_config(settings: &EngineConfig, pool: Option<PoolSize>, fuel: bool) -> Config {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    config.consume_fuel(fuel);
+
+    if let Some(opt_level) = settings.opt_level {
+        config.cranelift_opt_level(match opt_level {
+            OptLevel::None => wasmtime::OptLevel::None,
+            OptLevel::Speed => wasmtime::OptLevel::Speed,
+            OptLevel::SpeedAndSize => wasmtime::OptLevel::SpeedAndSize,
+        });
+    }
+
+    if let Some(parallel) = settings.parallel_compilation {
+        config.parallel_compilation(parallel);
+    }
+
+    if let Some(debug_info) = settings.debug_info {
+        config.debug_info(debug_info);
+    }
+
+    if let Some(pool) = pool {
+        config.allocation_strategy(pool.strategy());
+    }
+
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_cache() -> EngineConfig {
+        EngineConfig {
+            cache: Some(false),
+            ..EngineConfig::default()
+        }
+    }
+
+    #[test]
+    fn js_engine_is_shared() {
+        let runtime = Runtime::new(&no_cache(), Path::new("."), None, false).unwrap();
+        assert!(runtime.js_engine.lock().unwrap().is_none());
+
+        runtime.clone().js_engine().unwrap();
+        assert!(runtime.js_engine.lock().unwrap().is_some());
+    }
+
+    #[test]
+    fn js_engine_protocol() {
+        let runtime = Runtime::new(&no_cache(), Path::new("."), None, false).unwrap();
+
+        assert_eq!(runtime.js_protocol().unwrap(), Some(Protocol::LATEST));
+    }
+}
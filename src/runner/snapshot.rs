@@ -0,0 +1,102 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// Function of the QuickJS engine that evaluates the handler source. The
+/// engines without it don't support snapshots
+pub const INIT_FUNCTION: &str = "wizer.initialize";
+
+/// The Wizer CLI. It takes the snapshots when it's installed
+const WIZER_COMMAND: &str = "wizer";
+
+/// Folder in the engine file system that contains the handler source
+const GUEST_FOLDER: &str = "/src";
+
+/// Name of the handler source file in the guest folder
+const SOURCE_FILE: &str = "handler.js";
+
+/// Identifies the temporary folders of the snapshots in progress
+static SNAPSHOTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Check if the Wizer CLI is available. Without it, the JavaScript handlers
+/// don't use snapshots. The first check reports when they are disabled
+pub fn is_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+    *AVAILABLE.get_or_init(|| {
+        let available = Command::new(WIZER_COMMAND)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+
+        if !available {
+            eprintln!(
+                "⚠️  The \"{}\" command is not installed. JavaScript handlers will evaluate their source on every request instead of using snapshots",
+                WIZER_COMMAND
+            );
+        }
+
+        available
+    })
+}
+
+/// Pre-initialize the QuickJS engine with the given handler. The Wizer CLI runs
+/// the engine initialization function, which evaluates the polyfill and the handler
+/// source, and returns a new module with the resulting memory. Running the new
+/// module only calls the handler entrypoint with the request.
+pub fn initialize(engine: &[u8], source: &str) -> Result<Vec<u8>> {
+    let folder = temp_folder();
+    let result = run_wizer(&folder, engine, source);
+    let _ = fs::remove_dir_all(&folder);
+
+    result
+}
+
+// Write the engine and the handler source to the given folder and take the
+// snapshot with the Wizer CLI
+fn run_wizer(folder: &Path, engine: &[u8], source: &str) -> Result<Vec<u8>> {
+    let input = folder.join("engine.wasm");
+    let output = folder.join("snapshot.wasm");
+    let source_folder = folder.join("src");
+
+    fs::create_dir_all(&source_folder)?;
+    fs::write(&input, engine)?;
+    fs::write(source_folder.join(SOURCE_FILE), source)?;
+
+    let result = Command::new(WIZER_COMMAND)
+        .arg("--allow-wasi")
+        .args(["--wasm-bulk-memory", "true"])
+        .arg("--mapdir")
+        .arg(format!("{}::{}", GUEST_FOLDER, source_folder.display()))
+        .arg("-o")
+        .arg(&output)
+        .arg(&input)
+        .output()?;
+
+    if !result.status.success() {
+        return Err(anyhow::Error::msg(format!(
+            "Error evaluating the handler: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+
+    Ok(fs::read(&output)?)
+}
+
+// A new temporary folder to pass the handler source to the engine
+fn temp_folder() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "wws-snapshot-{}-{}",
+        std::process::id(),
+        SNAPSHOTS.fetch_add(1, Ordering::SeqCst)
+    ))
+}
@@ -0,0 +1,153 @@
+// Copyright 2022 VMware, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::router::IgnoreRules;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Time between two checks of the project files
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The state of a file when the watcher checked it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct FileState {
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+/// Detects the changes in the project folder. It polls the file metadata, so
+/// it works in every platform and file system without extra dependencies.
+/// Hidden folders and the default ignored folders (`node_modules`, `target`)
+/// are not watched.
+pub struct Watcher {
+    /// The project folder
+    base_path: PathBuf,
+    /// The state of the files in the last check
+    files: HashMap<PathBuf, FileState>,
+    /// Skip the folders that never contain routes
+    ignore: IgnoreRules,
+}
+
+impl Watcher {
+    /// Initialize the watcher with the current state of the project folder
+    pub fn new(base_path: &Path) -> Self {
+        let mut watcher = Self {
+            base_path: base_path.to_path_buf(),
+            files: HashMap::new(),
+            ignore: IgnoreRules::default(),
+        };
+        watcher.files = watcher.scan();
+
+        watcher
+    }
+
+    /// Returns the files that were added, modified or removed since the last check
+    pub fn changes(&mut self) -> Vec<PathBuf> {
+        let files = self.scan();
+        let mut changes: Vec<PathBuf> = files
+            .iter()
+            .filter(|(path, state)| self.files.get(*path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .chain(
+                self.files
+                    .keys()
+                    .filter(|path| !files.contains_key(*path))
+                    .cloned(),
+            )
+            .collect();
+        changes.sort();
+
+        self.files = files;
+        changes
+    }
+
+    /// Check the project folder periodically and call the given function when
+    /// there are changes. It blocks the current thread.
+    pub fn watch<F>(mut self, mut on_change: F)
+    where
+        F: FnMut(Vec<PathBuf>),
+    {
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+
+            let changes = self.changes();
+            if !changes.is_empty() {
+                on_change(changes);
+            }
+        }
+    }
+
+    // Retrieve the current state of the project files
+    fn scan(&self) -> HashMap<PathBuf, FileState> {
+        let mut files = HashMap::new();
+        self.scan_folder(&self.base_path, &mut files);
+
+        files
+    }
+
+    // Add the state of the files in the given folder and its subfolders
+    fn scan_folder(&self, folder: &Path, files: &mut HashMap<PathBuf, FileState>) {
+        let entries = match fs::read_dir(folder) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            if metadata.is_dir() {
+                let relative = path.strip_prefix(&self.base_path).unwrap_or(&path);
+                let is_hidden = entry.file_name().to_string_lossy().starts_with('.');
+
+                // Check a file inside to apply the folder rules
+                if !is_hidden && !self.ignore.is_ignored(&relative.join("_")) {
+                    self.scan_folder(&path, files);
+                }
+            } else {
+                files.insert(
+                    path,
+                    FileState {
+                        modified: metadata.modified().ok(),
+                        size: metadata.len(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watcher_detects_changes() {
+        let base_path = std::env::temp_dir().join(format!("wws-watcher-{}", std::process::id()));
+        fs::create_dir_all(base_path.join("api")).unwrap();
+        fs::create_dir_all(base_path.join("node_modules")).unwrap();
+        fs::write(base_path.join("index.js"), "a").unwrap();
+
+        let mut watcher = Watcher::new(&base_path);
+        assert!(watcher.changes().is_empty());
+
+        fs::write(base_path.join("api/users.js"), "b").unwrap();
+        fs::write(base_path.join("index.js"), "ab").unwrap();
+        fs::write(base_path.join("node_modules/lib.js"), "c").unwrap();
+        assert_eq!(
+            watcher.changes(),
+            vec![base_path.join("api/users.js"), base_path.join("index.js")]
+        );
+
+        fs::remove_file(base_path.join("api/users.js")).unwrap();
+        assert_eq!(watcher.changes(), vec![base_path.join("api/users.js")]);
+
+        fs::remove_dir_all(&base_path).unwrap();
+    }
+}